@@ -15,31 +15,31 @@ use domain::{
     rdata,
 };
 
-pub(crate) struct Iter<'a>(RecordSection<'a, Vec<u8>>);
+/// The addresses of the answer section, empty if it cannot be parsed.
+pub(crate) struct Iter<'a>(Option<RecordSection<'a, Vec<u8>>>);
 
 impl<'a> Iterator for Iter<'a> {
     type Item = (IpAddr, Duration);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(Ok(record)) = self.0.next() {
-            match record.rtype() {
-                Rtype::A => {
-                    let ttl = record.ttl().into_duration();
-                    let data: rdata::A = record.into_record().unwrap().unwrap().into_data();
-                    let addr = IpAddr::V4(data.addr());
-                    Some((addr, ttl))
-                }
-                Rtype::AAAA => {
-                    let ttl = record.ttl().into_duration();
-                    let data: rdata::Aaaa = record.into_record().unwrap().unwrap().into_data();
-                    let addr = IpAddr::V6(data.addr());
-                    Some((addr, ttl))
-                }
-                _ => None,
-            }
-        } else {
-            None
+        // The addresses follow the CNAME records of the chain, if any.
+        while let Some(Ok(record)) = self.0.as_mut()?.next() {
+            let ttl = record.ttl().into_duration();
+            // Records with malformed data are skipped.
+            let addr = match record.rtype() {
+                Rtype::A => match record.into_record::<rdata::A>() {
+                    Ok(Some(record)) => IpAddr::V4(record.data().addr()),
+                    _ => continue,
+                },
+                Rtype::AAAA => match record.into_record::<rdata::Aaaa>() {
+                    Ok(Some(record)) => IpAddr::V6(record.data().addr()),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            return Some((addr, ttl));
         }
+        None
     }
 }
 
@@ -49,7 +49,24 @@ pub(crate) struct IpAddresses {
 
 impl IpAddresses {
     pub(crate) fn iter(&self) -> Iter {
-        Iter(self.message.answer().unwrap())
+        Iter(self.message.answer().ok())
+    }
+
+    /// The owner of the addresses, which is the last name of the CNAME chain.
+    pub(crate) fn canonical_name(&self) -> Option<String> {
+        let mut answer = self.message.answer().ok()?;
+        answer.find_map(|record| match record {
+            Ok(record) if matches!(record.rtype(), Rtype::A | Rtype::AAAA) => {
+                let name = record.owner().to_string();
+                Some(String::from(name.trim_end_matches('.')))
+            }
+            _ => None,
+        })
+    }
+
+    /// Whether the AD bit is set.
+    pub(crate) fn authenticated(&self) -> bool {
+        self.message.header().ad()
     }
 }
 
@@ -58,3 +75,54 @@ impl From<Message<Vec<u8>>> for IpAddresses {
         Self { message }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    /// A response for `www.example.com. A`, a CNAME to `example.net.` followed by its address.
+    fn cname_response() -> Vec<u8> {
+        let mut buf = vec![0, 1, 0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 0];
+        buf.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+        // www.example.com. 300 IN CNAME example.net.
+        buf.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 1, 44, 0, 13]);
+        let target = buf.len();
+        buf.extend_from_slice(b"\x07example\x03net\x00");
+        // example.net. 60 IN A 192.0.2.1
+        buf.extend_from_slice(&[0xc0, target as u8, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        buf.extend_from_slice(&[192, 0, 2, 1]);
+        buf
+    }
+
+    #[test]
+    fn malformed() {
+        // The address of an A record is one byte short.
+        let mut buf = cname_response();
+        let len = buf.len();
+        buf[len - 5] = 3;
+        buf.truncate(len - 1);
+        let addrs = IpAddresses::from(Message::from_octets(buf).unwrap());
+        assert_eq!(addrs.iter().count(), 0);
+
+        // The question section is cut short.
+        let mut buf = cname_response();
+        buf.truncate(20);
+        let addrs = IpAddresses::from(Message::from_octets(buf).unwrap());
+        assert_eq!(addrs.iter().count(), 0);
+        assert_eq!(addrs.canonical_name(), None);
+    }
+
+    #[test]
+    fn cname_chain() {
+        let message = Message::from_octets(cname_response()).unwrap();
+        let addrs = IpAddresses::from(message);
+        let expected = (
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            Duration::from_secs(60),
+        );
+        assert_eq!(addrs.iter().collect::<Vec<_>>(), [expected]);
+        assert_eq!(addrs.canonical_name().as_deref(), Some("example.net"));
+    }
+}
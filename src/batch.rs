@@ -0,0 +1,92 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::Poll,
+};
+
+use crate::{errors::Error, lookups::udp::Sockets, LookupOptions, LookupResult, StubResolver};
+
+impl StubResolver {
+    /// Looks up the addresses of the hosts concurrently, with at most `limit` lookups in flight.
+    ///
+    /// The results are in the order of the hosts, and a failed lookup does not abort the others.
+    /// The lookups share a UDP socket per nameserver, whose responses are routed by message ID,
+    /// as well as the health of the nameservers, and the lookups of the same name share a single query.
+    pub async fn lookup_many<S: AsRef<str>>(
+        &self,
+        hosts: impl IntoIterator<Item = S>,
+        limit: usize,
+    ) -> Vec<Result<LookupResult, Error>> {
+        self.lookup_many_with(hosts, limit, LookupOptions::default())
+            .await
+    }
+
+    /// Like [`StubResolver::lookup_many`], with the options applied to every lookup.
+    pub async fn lookup_many_with<S: AsRef<str>>(
+        &self,
+        hosts: impl IntoIterator<Item = S>,
+        limit: usize,
+        options: LookupOptions,
+    ) -> Vec<Result<LookupResult, Error>> {
+        let sockets = Sockets::default();
+        let lookups = hosts.into_iter().map(|host| {
+            let host = host.as_ref().to_owned();
+            self.resolve_with_sockets(host, options.clone(), Some(sockets.clone()))
+        });
+        buffered(lookups, limit).await
+    }
+}
+
+/// Runs the futures concurrently, with at most `limit` of them at once, and returns their outputs in order.
+async fn buffered<F: Future>(futures: impl IntoIterator<Item = F>, limit: usize) -> Vec<F::Output> {
+    let limit = limit.max(1);
+    let mut pending = futures.into_iter().enumerate();
+    let mut running: Vec<(usize, Pin<Box<F>>)> = Vec::new();
+    let mut outputs: Vec<Option<F::Output>> = Vec::new();
+
+    poll_fn(move |cx| loop {
+        while running.len() < limit {
+            match pending.next() {
+                Some((idx, future)) => {
+                    outputs.push(None);
+                    running.push((idx, Box::pin(future)));
+                }
+                None => break,
+            }
+        }
+
+        let mut idx = 0;
+        let mut completed = false;
+        while idx < running.len() {
+            let (position, future) = &mut running[idx];
+            match future.as_mut().poll(cx) {
+                Poll::Ready(output) => {
+                    outputs[*position] = Some(output);
+                    drop(running.swap_remove(idx));
+                    completed = true;
+                }
+                Poll::Pending => idx += 1,
+            }
+        }
+
+        if running.is_empty() {
+            let outputs = std::mem::take(&mut outputs);
+            return Poll::Ready(outputs.into_iter().flatten().collect());
+        }
+        // Start the next futures in place of the completed ones.
+        if !completed {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
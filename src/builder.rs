@@ -0,0 +1,218 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    errors::Error, hedge::Strategy, nameserver::Nameserver, sort::PolicyTable, NsSwitch,
+    SpecialUse, StubResolver, SystemdResolved,
+};
+
+/// Configures a [`StubResolver`].
+///
+/// Unless disabled with [`StubResolverBuilder::system_config`], the system configuration
+/// is loaded first, and the values set on the builder take precedence over it.
+/// The builder is kept by the resolver, so that the same overrides are applied on [`StubResolver::reload`].
+#[derive(Debug, Clone)]
+pub struct StubResolverBuilder {
+    pub(crate) system_config: bool,
+    pub(crate) environment: bool,
+    pub(crate) max_nameservers: usize,
+    pub(crate) max_search_domains: usize,
+    pub(crate) systemd_resolved: SystemdResolved,
+    pub(crate) nameservers: Vec<Nameserver>,
+    pub(crate) routes: Vec<(String, Vec<Nameserver>)>,
+    pub(crate) resolver_dir: Option<PathBuf>,
+    pub(crate) search: Option<Vec<String>>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) ndots: Option<u8>,
+    pub(crate) attempts: Option<u8>,
+    pub(crate) rotate: Option<bool>,
+    pub(crate) udp_payload_size: Option<u16>,
+    pub(crate) strategy: Strategy,
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) sort_addresses: bool,
+    pub(crate) policy_table: Option<PolicyTable>,
+    pub(crate) gai_conf: bool,
+    pub(crate) special_use: SpecialUse,
+    pub(crate) nsswitch: Option<NsSwitch>,
+}
+
+impl Default for StubResolverBuilder {
+    fn default() -> Self {
+        Self {
+            system_config: true,
+            environment: true,
+            max_nameservers: 3,
+            max_search_domains: 6,
+            systemd_resolved: SystemdResolved::default(),
+            nameservers: Vec::default(),
+            routes: Vec::default(),
+            resolver_dir: None,
+            search: None,
+            timeout: None,
+            ndots: None,
+            attempts: None,
+            rotate: None,
+            udp_payload_size: None,
+            strategy: Strategy::default(),
+            deadline: None,
+            sort_addresses: true,
+            policy_table: None,
+            gai_conf: false,
+            special_use: SpecialUse::default(),
+            nsswitch: None,
+        }
+    }
+}
+
+impl StubResolverBuilder {
+    /// Whether to read `/etc/hosts` and `/etc/resolv.conf`.
+    pub fn system_config(mut self, enabled: bool) -> Self {
+        self.system_config = enabled;
+        self
+    }
+
+    /// Whether to apply the `LOCALDOMAIN`, `RES_OPTIONS` and `HOSTALIASES` environment variables, like glibc.
+    ///
+    /// They are applied on top of `/etc/resolv.conf`, and the values set on the builder take precedence over them.
+    pub fn environment(mut self, enabled: bool) -> Self {
+        self.environment = enabled;
+        self
+    }
+
+    /// How many nameservers of `/etc/resolv.conf` are used, 3 by default like glibc.
+    pub fn max_nameservers(mut self, max: usize) -> Self {
+        self.max_nameservers = max;
+        self
+    }
+
+    /// How many search domains of `/etc/resolv.conf` or `LOCALDOMAIN` are used, 6 by default like glibc.
+    pub fn max_search_domains(mut self, max: usize) -> Self {
+        self.max_search_domains = max;
+        self
+    }
+
+    /// Whether to bypass the stub of systemd-resolved (`127.0.0.53`), and talk to the upstream nameservers instead.
+    ///
+    /// The stub is kept if the upstream nameservers are not available.
+    pub fn systemd_resolved(mut self, mode: SystemdResolved) -> Self {
+        self.systemd_resolved = mode;
+        self
+    }
+
+    /// Adds a nameserver, the nameservers of `/etc/resolv.conf` are ignored if any is added.
+    pub fn nameserver(mut self, nameserver: impl Into<Nameserver>) -> Self {
+        self.nameservers.push(nameserver.into());
+        self
+    }
+
+    /// Sends the queries of the domain and its subdomains to the nameservers, rather than the default ones.
+    ///
+    /// The longest matching domain wins, and a route for `.` replaces the default nameservers.
+    pub fn route<N: Into<Nameserver>>(
+        mut self,
+        domain: impl Into<String>,
+        nameservers: impl IntoIterator<Item = N>,
+    ) -> Self {
+        let nameservers = nameservers.into_iter().map(Into::into).collect();
+        self.routes.push((domain.into(), nameservers));
+        self
+    }
+
+    /// Reads routes from a directory of files named after their domain, like `/etc/resolver` on macOS.
+    ///
+    /// The `nameserver`, `port` and `timeout` directives of the files are supported.
+    /// The routes added with [`StubResolverBuilder::route`] take precedence over them.
+    pub fn resolver_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.resolver_dir = Some(path.into());
+        self
+    }
+
+    pub fn search<S: Into<String>>(mut self, domains: impl IntoIterator<Item = S>) -> Self {
+        self.search = Some(domains.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn ndots(mut self, ndots: u8) -> Self {
+        self.ndots = Some(ndots);
+        self
+    }
+
+    pub fn attempts(mut self, attempts: u8) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Spreads the queries across the nameservers in turn, rather than preferring the fastest ones,
+    /// like the `rotate` option of `resolv.conf`.
+    pub fn rotate(mut self, rotate: bool) -> Self {
+        self.rotate = Some(rotate);
+        self
+    }
+
+    pub fn udp_payload_size(mut self, size: u16) -> Self {
+        self.udp_payload_size = Some(size);
+        self
+    }
+
+    /// How the nameservers are queried, sequentially by default.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Bounds the total time of a lookup, across search domains, nameservers and attempts.
+    ///
+    /// Can be overridden per lookup with [`crate::LookupOptions::deadline`].
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Whether to order the addresses following RFC 6724, enabled by default.
+    pub fn sort_addresses(mut self, enabled: bool) -> Self {
+        self.sort_addresses = enabled;
+        self
+    }
+
+    /// Overrides the RFC 6724 policy table used to order the addresses.
+    pub fn policy_table(mut self, table: PolicyTable) -> Self {
+        self.policy_table = Some(table);
+        self
+    }
+
+    /// Whether to read the policy table from `/etc/gai.conf`, unless one is set with [`StubResolverBuilder::policy_table`].
+    pub fn gai_conf(mut self, enabled: bool) -> Self {
+        self.gai_conf = enabled;
+        self
+    }
+
+    /// Which special-use names are answered locally, all of them by default.
+    pub fn special_use(mut self, special_use: SpecialUse) -> Self {
+        self.special_use = special_use;
+        self
+    }
+
+    /// Overrides the order of the services of `/etc/nsswitch.conf`.
+    pub fn nsswitch(mut self, nsswitch: NsSwitch) -> Self {
+        self.nsswitch = Some(nsswitch);
+        self
+    }
+
+    pub async fn build(self) -> Result<StubResolver, Error> {
+        StubResolver::from_builder(self).await
+    }
+}
@@ -0,0 +1,144 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::{poll_fn, Future},
+    rc::Rc,
+    task::{Poll, Waker},
+    time::Duration,
+};
+
+use domain::base::Rtype;
+
+use crate::{AddressFamily, LookupOptions, Protocol};
+
+/// Identifies the queries that can be shared between concurrent lookups.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    name: String,
+    /// `None` for the A and AAAA queries of an address lookup.
+    rtype: Option<Rtype>,
+    family: AddressFamily,
+    timeout: Option<Duration>,
+    attempts: Option<u8>,
+    recursion_desired: bool,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    protocol: Option<Protocol>,
+}
+
+impl Key {
+    pub(crate) fn new(name: &str, rtype: Option<Rtype>, options: &LookupOptions) -> Self {
+        Self {
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            rtype,
+            family: options.family,
+            timeout: options.timeout,
+            attempts: options.attempts,
+            recursion_desired: options.recursion_desired,
+            dnssec_ok: options.dnssec_ok,
+            checking_disabled: options.checking_disabled,
+            protocol: options.protocol,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: RefCell<Option<T>>,
+    done: Cell<bool>,
+    wakers: RefCell<Vec<Waker>>,
+}
+
+/// The in-flight queries, shared by the concurrent lookups on the same thread.
+#[derive(Debug)]
+pub(crate) struct Inflight<T>(RefCell<HashMap<Key, Rc<Slot<T>>>>);
+
+impl<T> Default for Inflight<T> {
+    fn default() -> Self {
+        Self(RefCell::default())
+    }
+}
+
+impl<T: Clone> Inflight<T> {
+    /// Runs the query, unless an identical one is in flight, in which case its result is awaited instead.
+    ///
+    /// Errors are not shared, if the query fails or is dropped, the waiting lookups run it themselves.
+    pub(crate) async fn run<E, F, Fut>(&self, key: Key, query: F) -> Result<T, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut query = Some(query);
+        loop {
+            let inflight = self.0.borrow().get(&key).cloned();
+            if let Some(slot) = inflight {
+                poll_fn(|cx| {
+                    if slot.done.get() {
+                        return Poll::Ready(());
+                    }
+                    let mut wakers = slot.wakers.borrow_mut();
+                    if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                        wakers.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                })
+                .await;
+                if let Some(value) = slot.value.borrow().clone() {
+                    return Ok(value);
+                }
+                continue;
+            }
+
+            let slot = Rc::new(Slot {
+                value: RefCell::new(None),
+                done: Cell::new(false),
+                wakers: RefCell::default(),
+            });
+            self.0.borrow_mut().insert(key.clone(), slot.clone());
+            let _guard = Guard {
+                inflight: self,
+                key: &key,
+                slot: &slot,
+            };
+            let query = query.take().expect("the query runs once");
+            let result = query().await;
+            if let Ok(value) = &result {
+                *slot.value.borrow_mut() = Some(value.clone());
+            }
+            return result;
+        }
+    }
+}
+
+/// Releases the waiting lookups once the query has completed or was dropped.
+struct Guard<'a, T> {
+    inflight: &'a Inflight<T>,
+    key: &'a Key,
+    slot: &'a Rc<Slot<T>>,
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        let mut inflight = self.inflight.0.borrow_mut();
+        if inflight
+            .get(self.key)
+            .is_some_and(|slot| Rc::ptr_eq(slot, self.slot))
+        {
+            inflight.remove(self.key);
+        }
+        self.slot.done.set(true);
+        for waker in self.slot.wakers.take() {
+            waker.wake();
+        }
+    }
+}
@@ -41,6 +41,10 @@ pub enum Error {
         source: domain::base::message::ShortMessage,
     },
     #[snafu(context(false))]
+    MessageParseError {
+        source: domain::base::wire::ParseError,
+    },
+    #[snafu(context(false))]
     MessagePushError {
         source: domain::base::message_builder::PushError,
     },
@@ -57,9 +61,35 @@ pub enum Error {
         source: domain::base::name::PushError,
     },
     AppendError {},
+    InvalidSocketAddr {
+        addr: String,
+    },
+    InvalidSvcb {},
     QueryTooLarge {},
     InvalidMessageID {
         expected: u16,
         found: u16,
     },
+    NoResponse {},
+    /// The nameserver failed or refused the query, e.g. with SERVFAIL or REFUSED.
+    ResponseCode {
+        rcode: domain::base::iana::Rcode,
+    },
+    /// The host has no addresses to connect to.
+    NoAddresses {},
+    /// The deadline of the lookup has elapsed.
+    Timeout {
+        progress: crate::Progress,
+    },
+    /// The lookup was cancelled through its [`crate::CancellationToken`].
+    Cancelled {
+        progress: crate::Progress,
+    },
+    UnsupportedProtocol {
+        protocol: crate::Protocol,
+    },
+    /// The name is special-use, and is never sent to the nameservers, see [`crate::SpecialUse`].
+    SpecialUseName {
+        name: String,
+    },
 }
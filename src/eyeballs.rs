@@ -0,0 +1,127 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::Poll,
+    time::Duration,
+};
+
+use monoio::net::TcpStream;
+
+use crate::{errors::Error, AddressFamily, LookupOptions, LookupResult, StubResolver};
+
+/// How long to wait for the AAAA records once the A records are available.
+const RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+/// How long to wait for a connection attempt before starting the next one.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+const IPV6: usize = 0;
+const IPV4: usize = 1;
+
+type Resolution<'a> = Pin<Box<dyn Future<Output = Result<LookupResult, Error>> + 'a>>;
+type Attempt = Pin<Box<dyn Future<Output = std::io::Result<TcpStream>>>>;
+type Timer = Pin<Box<dyn Future<Output = ()>>>;
+
+impl StubResolver {
+    /// Connects to the host following Happy Eyeballs v2 (RFC 8305).
+    ///
+    /// The AAAA and A records are looked up concurrently, and the connection attempts start as soon as
+    /// the first family is available, alternating between the families and staggered by 250ms.
+    pub async fn connect_tcp(&self, host: impl AsRef<str>, port: u16) -> Result<TcpStream, Error> {
+        let host = host.as_ref();
+        let ipv6 = self.resolve(host, LookupOptions::new().family(AddressFamily::Ipv6));
+        let ipv4 = self.resolve(host, LookupOptions::new().family(AddressFamily::Ipv4));
+        let mut resolutions: [Option<Resolution>; 2] = [Some(Box::pin(ipv6)), Some(Box::pin(ipv4))];
+        let mut queues: [VecDeque<IpAddr>; 2] = Default::default();
+        let mut family = IPV6;
+        let mut resolution_delay: Option<Timer> = None;
+        let mut resolved = false;
+        let mut attempts: Vec<Attempt> = Vec::new();
+        let mut attempt_delay: Option<Timer> = None;
+        let mut last_error = None;
+
+        poll_fn(|cx| loop {
+            for idx in [IPV6, IPV4] {
+                let Some(resolution) = resolutions[idx].as_mut() else {
+                    continue;
+                };
+                if let Poll::Ready(result) = resolution.as_mut().poll(cx) {
+                    resolutions[idx] = None;
+                    match result {
+                        Ok(result) => queues[idx].extend(result.ips()),
+                        Err(err) => last_error = Some(err),
+                    }
+                }
+            }
+
+            // Start connecting once the AAAA records are available, or shortly after the A records.
+            if !resolved {
+                resolved = match (resolutions[IPV6].is_none(), resolutions[IPV4].is_none()) {
+                    (true, _) => true,
+                    (false, true) => resolution_delay
+                        .get_or_insert_with(|| Box::pin(monoio::time::sleep(RESOLUTION_DELAY)))
+                        .as_mut()
+                        .poll(cx)
+                        .is_ready(),
+                    (false, false) => false,
+                };
+            }
+
+            let mut idx = 0;
+            while idx < attempts.len() {
+                match attempts[idx].as_mut().poll(cx) {
+                    Poll::Ready(Ok(stream)) => return Poll::Ready(Ok(stream)),
+                    Poll::Ready(Err(err)) => {
+                        last_error = Some(Error::from(err));
+                        drop(attempts.swap_remove(idx));
+                        // Do not wait for the delay to try the next address.
+                        attempt_delay = None;
+                    }
+                    Poll::Pending => idx += 1,
+                }
+            }
+
+            if resolved {
+                let delay_elapsed = attempt_delay
+                    .as_mut()
+                    .map_or(true, |timer| timer.as_mut().poll(cx).is_ready());
+                if delay_elapsed {
+                    attempt_delay = None;
+                    // Alternate between the families, starting with IPv6.
+                    let next = queues[family]
+                        .pop_front()
+                        .map(|ip| (ip, family))
+                        .or_else(|| queues[1 - family].pop_front().map(|ip| (ip, 1 - family)));
+                    if let Some((ip, popped)) = next {
+                        family = 1 - popped;
+                        let addr = SocketAddr::new(ip, port);
+                        attempts.push(Box::pin(TcpStream::connect(addr)));
+                        attempt_delay =
+                            Some(Box::pin(monoio::time::sleep(CONNECTION_ATTEMPT_DELAY)));
+                        continue;
+                    }
+                }
+            }
+
+            let exhausted = attempts.is_empty()
+                && resolutions.iter().all(Option::is_none)
+                && queues.iter().all(VecDeque::is_empty);
+            if exhausted {
+                return Poll::Ready(Err(last_error.take().unwrap_or(Error::NoAddresses {})));
+            }
+            return Poll::Pending;
+        })
+        .await
+    }
+}
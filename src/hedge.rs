@@ -0,0 +1,90 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::Poll,
+    time::Duration,
+};
+
+/// How the nameservers are queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Query the next nameserver only once the previous one has failed.
+    #[default]
+    Sequential,
+    /// Also query the next nameserver if the previous one has not answered within the given delay.
+    Hedged(Duration),
+    /// Query all the nameservers at once.
+    Parallel,
+}
+
+/// Runs the futures according to the strategy, and returns the first successful result,
+/// or the last error if all of them have failed.
+///
+/// The futures that are still running once a result is available are dropped,
+/// which cancels their in-flight operations.
+pub(crate) async fn race<F, T, E>(
+    futures: impl IntoIterator<Item = F>,
+    strategy: Strategy,
+) -> Option<Result<T, E>>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let mut pending = futures.into_iter();
+    let mut running: Vec<Pin<Box<F>>> = Vec::new();
+    let mut timer: Option<Pin<Box<dyn Future<Output = ()>>>> = None;
+    let mut last = None;
+
+    poll_fn(move |cx| loop {
+        let mut idx = 0;
+        while idx < running.len() {
+            match running[idx].as_mut().poll(cx) {
+                Poll::Ready(Ok(value)) => return Poll::Ready(Some(Ok(value))),
+                Poll::Ready(Err(err)) => {
+                    last = Some(err);
+                    running.swap_remove(idx);
+                }
+                Poll::Pending => idx += 1,
+            }
+        }
+
+        let start_next = running.is_empty()
+            || match strategy {
+                Strategy::Sequential => false,
+                Strategy::Parallel => true,
+                Strategy::Hedged(_) => {
+                    let elapsed = timer
+                        .as_mut()
+                        .is_some_and(|timer| timer.as_mut().poll(cx).is_ready());
+                    if elapsed {
+                        timer = None;
+                    }
+                    elapsed
+                }
+            };
+        if !start_next {
+            return Poll::Pending;
+        }
+
+        match pending.next() {
+            Some(future) => {
+                running.push(Box::pin(future));
+                if let Strategy::Hedged(delay) = strategy {
+                    timer = Some(Box::pin(monoio::time::sleep(delay)));
+                }
+            }
+            None if running.is_empty() => return Poll::Ready(last.take().map(Err)),
+            None => return Poll::Pending,
+        }
+    })
+    .await
+}
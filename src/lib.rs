@@ -12,45 +12,125 @@
 #![cfg_attr(not(feature = "global"), forbid(unsafe_code))]
 
 mod addr;
+mod batch;
+mod builder;
+mod coalesce;
 mod errors;
+mod eyeballs;
+mod hedge;
 mod lookups;
+mod nameserver;
+mod nsswitch;
+mod options;
 mod parser;
 #[doc = include_str!("../README.md")]
 pub mod readme;
 mod resolvers;
-use std::{
-    collections::BTreeSet,
-    net::{IpAddr, SocketAddr},
-    time::Duration,
-};
+mod result;
+mod sockaddr;
+mod sort;
+mod srv;
+mod stats;
+mod svcb;
+#[cfg(feature = "watch")]
+mod watch;
+use std::{net::IpAddr, rc::Rc, time::Duration};
 
+pub use builder::StubResolverBuilder;
+pub use domain::base::{Message, Rtype};
 pub use errors::Error;
+pub use hedge::Strategy;
+pub use lookups::{Query, QueryFuture, Tcp, Transport, Udp};
+pub use nameserver::{Nameserver, Protocol};
+pub use nsswitch::{Action, NsSwitch, NssEntry, Service, Status};
+pub use options::{AddressFamily, CancellationToken, LookupOptions, Progress, Search};
+pub use parser::{Reloaded, SystemdResolved};
+pub use resolvers::SpecialUse;
+pub use result::{LookupResult, Source};
+pub use sort::PolicyTable;
+pub use srv::SrvTarget;
+pub use stats::NameserverStats;
+pub use svcb::{SvcbEndpoint, SvcbRecord};
+#[cfg(feature = "watch")]
+pub use watch::Watcher;
 
+/// A line of `/etc/hosts`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct HostEntry {
     pub ip: IpAddr,
-    pub hosts: BTreeSet<String>,
+    /// The first name after the address.
+    pub canonical: String,
+    /// The other names, in order, without duplicates.
+    pub aliases: Vec<String>,
 }
 
 impl HostEntry {
-    pub fn new(ip: IpAddr, hosts: impl Iterator<Item = String>) -> Self {
-        Self {
+    /// Like glibc, aliases that repeat a previous name (case-insensitively) are dropped.
+    pub fn new(
+        ip: IpAddr,
+        canonical: impl Into<String>,
+        aliases: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let canonical = canonical.into();
+        let mut this = Self {
             ip,
-            hosts: hosts.collect(),
+            canonical,
+            aliases: Vec::new(),
+        };
+        for alias in aliases {
+            if !this.names().any(|name| name.eq_ignore_ascii_case(&alias)) {
+                this.aliases.push(alias);
+            }
         }
+        this
+    }
+
+    /// The canonical name, followed by the aliases.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.canonical.as_str()).chain(self.aliases.iter().map(String::as_str))
     }
 }
 
 #[cfg(unix)]
 pub struct StubResolver {
-    entries: Vec<HostEntry>,
+    /// Replaced as a whole on reload, the lookups keep the snapshot they started with.
+    config: std::cell::RefCell<Rc<Config>>,
+    strategy: Strategy,
+    deadline: Option<Duration>,
+    special_use: SpecialUse,
+    stats: Rc<stats::Stats>,
+    lookups: coalesce::Inflight<LookupResult>,
+    queries: coalesce::Inflight<Option<Message<Vec<u8>>>>,
+}
+
+/// The configuration of a resolver, as loaded from the system files and the builder.
+#[derive(Debug, Clone)]
+struct Config {
+    hosts: resolvers::Hosts,
     search: Vec<String>,
-    nameservers: Vec<SocketAddr>,
+    /// Pairs of (network, netmask).
+    sortlist: Vec<(std::net::Ipv4Addr, std::net::Ipv4Addr)>,
+    /// The aliases of `HOSTALIASES`, by lowercase alias.
+    host_aliases: std::collections::HashMap<String, String>,
+    nameservers: Vec<Nameserver>,
+    routes: resolvers::Routes,
     timeout: Duration,
     ndots: u8,
     attempts: u8,
     rotate: bool,
     udp_payload_size: u16,
+    policy_table: Option<PolicyTable>,
+    nsswitch: NsSwitch,
+    hostname: Option<String>,
+    builder: StubResolverBuilder,
+    files: parser::Files,
+}
+
+impl StubResolver {
+    /// The current configuration.
+    fn config(&self) -> Rc<Config> {
+        self.config.borrow().clone()
+    }
 }
 
 #[cfg(feature = "global")]
@@ -59,12 +139,7 @@ thread_local! {
 }
 
 #[cfg(feature = "global")]
-pub async fn lookup<'a, B>(
-    host: impl AsRef<str> + std::borrow::Borrow<str> + 'a,
-) -> Result<B, Error>
-where
-    B: FromIterator<(IpAddr, Duration)> + Sized,
-{
+async fn global<'a>() -> Result<&'a StubResolver, Error> {
     let global = GLOBAL.with(|global| unsafe {
         std::ptr::NonNull::new_unchecked(
             global as *const _ as *mut local_sync::OnceCell<StubResolver>,
@@ -72,22 +147,105 @@ where
         .as_ref()
     });
 
-    let dns: &StubResolver = global.get_or_try_init(|| StubResolver::load()).await?;
-    dns.lookup(host).await
+    global.get_or_try_init(|| StubResolver::load()).await
+}
+
+#[cfg(feature = "global")]
+pub async fn lookup<'a, B>(
+    host: impl AsRef<str> + std::borrow::Borrow<str> + 'a,
+) -> Result<B, Error>
+where
+    B: FromIterator<(IpAddr, Duration)> + Sized,
+{
+    global().await?.lookup(host).await
+}
+
+#[cfg(feature = "global")]
+pub async fn lookup_with<'a, B>(
+    host: impl AsRef<str> + std::borrow::Borrow<str> + 'a,
+    options: LookupOptions,
+) -> Result<B, Error>
+where
+    B: FromIterator<(IpAddr, Duration)> + Sized,
+{
+    global().await?.lookup_with(host, options).await
+}
+
+#[cfg(feature = "global")]
+pub async fn resolve<'a>(
+    host: impl AsRef<str> + std::borrow::Borrow<str> + 'a,
+    options: LookupOptions,
+) -> Result<LookupResult, Error> {
+    global().await?.resolve(host, options).await
+}
+
+#[cfg(feature = "global")]
+pub async fn lookup_many<S: AsRef<str>>(
+    hosts: impl IntoIterator<Item = S>,
+    limit: usize,
+) -> Result<Vec<Result<LookupResult, Error>>, Error> {
+    Ok(global().await?.lookup_many(hosts, limit).await)
 }
 
 #[cfg(feature = "global")]
-pub async fn reload() -> Result<(), Error> {
+pub async fn resolve_socket_addrs<B>(addr: impl AsRef<str>, default_port: u16) -> Result<B, Error>
+where
+    B: FromIterator<(std::net::SocketAddr, Duration)>,
+{
+    global()
+        .await?
+        .resolve_socket_addrs(addr, default_port)
+        .await
+}
+
+#[cfg(feature = "global")]
+pub async fn lookup_srv(name: impl AsRef<str>) -> Result<Vec<SrvTarget>, Error> {
+    global().await?.lookup_srv(name).await
+}
+
+#[cfg(feature = "global")]
+pub async fn query(name: impl AsRef<str>, rtype: Rtype) -> Result<Message<Vec<u8>>, Error> {
+    global()
+        .await?
+        .query(name, rtype, LookupOptions::default())
+        .await
+}
+
+#[cfg(feature = "global")]
+pub async fn resolve_https(host: impl AsRef<str>, port: u16) -> Result<Vec<SvcbEndpoint>, Error> {
+    global().await?.resolve_https(host, port).await
+}
+
+#[cfg(feature = "global")]
+pub async fn connect_tcp(
+    host: impl AsRef<str>,
+    port: u16,
+) -> Result<monoio::net::TcpStream, Error> {
+    global().await?.connect_tcp(host, port).await
+}
+
+#[cfg(feature = "global")]
+pub async fn reload() -> Result<Reloaded, Error> {
     let global = GLOBAL.with(|global| unsafe {
         std::ptr::NonNull::new_unchecked(
             global as *const _ as *mut local_sync::OnceCell<StubResolver>,
         )
-        .as_mut()
+        .as_ref()
     });
 
-    if let Some(dns) = global.get_mut() {
+    if let Some(dns) = global.get() {
         dns.reload().await
     } else {
-        Ok(())
+        Ok(Reloaded::default())
+    }
+}
+
+/// Reloads the global instance whenever `/etc/resolv.conf` or `/etc/hosts` change, see [`Watcher`].
+#[cfg(all(feature = "global", feature = "watch"))]
+pub async fn watch() -> Result<(), Error> {
+    let mut watcher = global().await?.watcher()?;
+    loop {
+        watcher.changed().await?;
+        reload().await?;
     }
 }
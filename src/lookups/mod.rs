@@ -8,5 +8,85 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 //
 
+use std::{future::Future, net::SocketAddr, pin::Pin, rc::Rc, time::Duration};
+
+use crate::errors::Error;
+
 pub(crate) mod tcp;
 pub(crate) mod udp;
+
+const MIN_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The future returned by [`Transport::query`].
+pub type QueryFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>, Error>> + 'a>>;
+
+/// A single wire-format query to be sent to an upstream nameserver.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Query {
+    /// The message ID, the response must carry the same ID.
+    pub id: u16,
+    /// The encoded DNS message.
+    pub message: Rc<Vec<u8>>,
+    /// The upstream nameserver.
+    pub nameserver: SocketAddr,
+    /// The name to verify the server certificate against, if any.
+    pub tls_name: Option<String>,
+    /// How many times the query may be sent before giving up.
+    pub attempts: u8,
+    /// How long to wait for a response on the first attempt, see [`Query::attempt_timeout`].
+    pub timeout: Duration,
+    /// The number of nameservers the lookup may go through.
+    pub nameservers: usize,
+    /// The advertised EDNS(0) UDP payload size.
+    pub udp_payload_size: u16,
+}
+
+impl Query {
+    /// How long to wait for a response on the given (zero-based) attempt.
+    ///
+    /// Like glibc, the timeout doubles on every retransmission, and is divided across the nameservers
+    /// after the first attempt, but it is never shorter than a second (or than the timeout if it is shorter),
+    /// nor longer than a minute (or than the timeout if it is longer).
+    pub fn attempt_timeout(&self, attempt: u8) -> Duration {
+        if attempt == 0 {
+            return self.timeout;
+        }
+        let factor = 1u32 << attempt.min(16);
+        let nameservers = u32::try_from(self.nameservers).unwrap_or(u32::MAX).max(1);
+        let timeout = self.timeout.saturating_mul(factor) / nameservers;
+        let min = self.timeout.min(MIN_RETRANSMIT_TIMEOUT);
+        let max = self.timeout.max(MAX_RETRANSMIT_TIMEOUT);
+        timeout.clamp(min, max)
+    }
+}
+
+/// A way of delivering a wire-format query to a nameserver.
+///
+/// Returns the wire-format response, or `None` if no usable response was received.
+pub trait Transport {
+    fn query<'a>(&'a self, query: &'a Query) -> QueryFuture<'a>;
+}
+
+/// Plain DNS over UDP (RFC 1035).
+///
+/// Yields `None` on truncated responses, so that the caller may retry over [`Tcp`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Udp;
+
+impl Transport for Udp {
+    fn query<'a>(&'a self, query: &'a Query) -> QueryFuture<'a> {
+        Box::pin(udp::query(query, None))
+    }
+}
+
+/// Plain DNS over TCP (RFC 7766).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tcp;
+
+impl Transport for Tcp {
+    fn query<'a>(&'a self, query: &'a Query) -> QueryFuture<'a> {
+        Box::pin(tcp::query(query))
+    }
+}
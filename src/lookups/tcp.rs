@@ -8,29 +8,30 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 //
 
-use std::rc::Rc;
-use std::{net::SocketAddr, time::Duration};
-
 use domain::base::Message;
 use monoio::{
     io::{AsyncReadRentExt, AsyncWriteRentExt},
     net::TcpStream,
 };
 
-use crate::{addr::IpAddresses, errors::Error};
+use super::Query;
+use crate::errors::Error;
 
 /// Query a nameserver for the given question, using the TCP protocol.
 #[cold]
+pub(crate) async fn query(query: &Query) -> Result<Option<Vec<u8>>, Error> {
+    monoio::time::timeout(query.timeout, exchange(query)).await?
+}
+
+async fn exchange(query: &Query) -> Result<Option<Vec<u8>>, Error> {
+    let Query {
+        id,
+        ref message,
+        ref nameserver,
+        ..
+    } = *query;
 
-pub(crate) async fn query(
-    id: u16,
-    query: Rc<Vec<u8>>,
-    nameserver: &SocketAddr,
-    _attempts: u8,
-    _timeout_duration: Duration,
-    _udp_payload_size: u16,
-) -> Result<Option<IpAddresses>, Error> {
-    if query.len() > u16::MAX as usize {
+    if message.len() > u16::MAX as usize {
         return Err(Error::QueryTooLarge {});
     }
 
@@ -38,12 +39,12 @@ pub(crate) async fn query(
     let mut socket = TcpStream::connect(nameserver).await?;
 
     // Write the length of the query.
-    let len_bytes = Vec::from((query.len() as u16).to_be_bytes());
+    let len_bytes = Vec::from((message.len() as u16).to_be_bytes());
     let (result, mut len_bytes) = socket.write_all(len_bytes).await;
     let _ = result?;
 
     // Write the query.
-    let (result, _) = socket.write_all(query).await;
+    let (result, _) = socket.write_all(message.clone()).await;
     let _ = result?;
 
     // Read the length of the response.
@@ -63,6 +64,5 @@ pub(crate) async fn query(
     if message.header().id() != id {
         return Ok(None);
     }
-    let ip_addresses = IpAddresses::from(message);
-    Ok(Some(ip_addresses))
+    Ok(Some(message.into_octets()))
 }
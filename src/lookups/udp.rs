@@ -9,47 +9,190 @@
 //
 
 use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::poll_fn,
+    io::ErrorKind,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     rc::Rc,
+    task::{Poll, Waker},
     time::Duration,
 };
 
 use domain::base::Message;
 use monoio::{io::Canceller, net::udp::UdpSocket};
 
-use crate::{addr::IpAddresses, errors::Error};
+use super::Query;
+use crate::errors::Error;
 
-/// Query a nameserver for the given question, using the UDP protocol.
+/// The UDP sockets shared by the queries of a batch, one per nameserver.
 ///
-/// Returns `None` if the UDP query failed and TCP should be used instead.
-pub(crate) async fn query(
+/// The responses of a socket are read by a task, and routed to the queries by their message ID.
+/// A socket is closed once its last query is done.
+#[derive(Clone, Default)]
+pub(crate) struct Sockets(Rc<RefCell<HashMap<SocketAddr, Rc<Shared>>>>);
+
+impl std::fmt::Debug for Sockets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Sockets")
+            .field(&self.0.borrow().keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+struct Shared {
+    socket: UdpSocket,
+    /// The largest payload size of the queries.
+    udp_payload_size: Cell<u16>,
+    /// The queries awaiting a response, by message ID.
+    pending: RefCell<HashMap<u16, Slot>>,
+    /// Cancels the read of the task once the socket is closed.
+    canceller: RefCell<Option<Canceller>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    response: Option<Vec<u8>>,
+    waker: Option<Waker>,
+}
+
+impl Sockets {
+    /// Registers the query on the socket of its nameserver, or `None` if its ID is already in flight there.
+    fn register(&self, query: &Query) -> Result<Option<Registration>, Error> {
+        let mut sockets = self.0.borrow_mut();
+        let shared = match sockets.get(&query.nameserver) {
+            Some(shared) if shared.pending.borrow().contains_key(&query.id) => return Ok(None),
+            Some(shared) => shared.clone(),
+            None => {
+                let shared = Rc::new(Shared {
+                    socket: bind(query.nameserver)?,
+                    udp_payload_size: Cell::new(0),
+                    pending: RefCell::default(),
+                    canceller: RefCell::new(Some(Canceller::new())),
+                });
+                sockets.insert(query.nameserver, shared.clone());
+                monoio::spawn(route(self.clone(), shared.clone(), query.nameserver));
+                shared
+            }
+        };
+        shared
+            .pending
+            .borrow_mut()
+            .insert(query.id, Slot::default());
+        let udp_payload_size = shared.udp_payload_size.get().max(query.udp_payload_size);
+        shared.udp_payload_size.set(udp_payload_size);
+        Ok(Some(Registration {
+            sockets: self.clone(),
+            shared,
+            nameserver: query.nameserver,
+            id: query.id,
+        }))
+    }
+
+    /// Forgets the socket, unless it was already replaced.
+    fn remove(&self, nameserver: SocketAddr, shared: &Rc<Shared>) {
+        let mut sockets = self.0.borrow_mut();
+        if sockets
+            .get(&nameserver)
+            .is_some_and(|socket| Rc::ptr_eq(socket, shared))
+        {
+            sockets.remove(&nameserver);
+        }
+    }
+}
+
+/// Reads the responses of the shared socket, until it is closed.
+async fn route(sockets: Sockets, shared: Rc<Shared>, nameserver: SocketAddr) {
+    loop {
+        let Some(handle) = shared.canceller.borrow().as_ref().map(Canceller::handle) else {
+            return;
+        };
+        let buf = Vec::with_capacity(shared.udp_payload_size.get() as usize);
+        let (result, buf) = shared.socket.cancelable_recv_from(buf, handle).await;
+        let from = match result {
+            Ok((_, from)) => from,
+            // The queries still waiting retry on a new socket.
+            Err(_) => return sockets.remove(nameserver, &shared),
+        };
+        // The responses from other addresses, or to unknown queries, are dropped.
+        if from.ip() != nameserver.ip() || from.port() != nameserver.port() {
+            continue;
+        }
+        let Some(&[high, low]) = buf.get(..2) else {
+            continue;
+        };
+        let id = u16::from_be_bytes([high, low]);
+        if let Some(slot) = shared.pending.borrow_mut().get_mut(&id) {
+            slot.response = Some(buf);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// A query awaiting its response on a shared socket.
+struct Registration {
+    sockets: Sockets,
+    shared: Rc<Shared>,
+    nameserver: SocketAddr,
     id: u16,
-    query: Rc<Vec<u8>>,
-    nameserver: &SocketAddr,
-    attempts: u8,
-    timeout_duration: Duration,
-    udp_payload_size: u16,
-) -> Result<Option<IpAddresses>, Error> {
-    // Write the query to the nameserver address.
-    let bind_address: SocketAddr = match nameserver.ip() {
-        std::net::IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
-        std::net::IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
-    };
-    let socket = UdpSocket::bind(bind_address)?;
+}
 
-    async fn send(
-        socket: &UdpSocket,
-        buf: Rc<Vec<u8>>,
-        server: SocketAddr,
-    ) -> Result<usize, std::io::Error> {
+impl Registration {
+    async fn response(&self) -> Vec<u8> {
+        poll_fn(|cx| {
+            let mut pending = self.shared.pending.borrow_mut();
+            let slot = pending.entry(self.id).or_default();
+            match slot.response.take() {
+                Some(response) => Poll::Ready(response),
+                None => {
+                    slot.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut pending = self.shared.pending.borrow_mut();
+        pending.remove(&self.id);
+        if pending.is_empty() {
+            self.sockets.remove(self.nameserver, &self.shared);
+            if let Some(canceller) = self.shared.canceller.take() {
+                canceller.cancel();
+            }
+        }
+    }
+}
+
+/// The socket of a query, either its own or the one it shares with the other queries of a batch.
+enum Socket {
+    Own(UdpSocket),
+    Shared(Registration),
+}
+
+impl Socket {
+    async fn send(&self, buf: Rc<Vec<u8>>, server: SocketAddr) -> Result<usize, std::io::Error> {
+        let socket = match self {
+            Self::Own(socket) => socket,
+            Self::Shared(registration) => &registration.shared.socket,
+        };
         socket.send_to(buf, server).await.0
     }
 
-    async fn recv(
-        socket: &UdpSocket,
-        buf: Vec<u8>,
-        t: Duration,
-    ) -> (Result<usize, std::io::Error>, Vec<u8>) {
+    async fn recv(&self, capacity: usize, t: Duration) -> Result<Vec<u8>, std::io::Error> {
+        let socket = match self {
+            Self::Own(socket) => socket,
+            Self::Shared(registration) => {
+                return monoio::time::timeout(t, registration.response())
+                    .await
+                    .map_err(|_| ErrorKind::TimedOut.into());
+            }
+        };
         let canceller = Canceller::new();
         let handle = canceller.handle();
         let cancel_io = async move {
@@ -57,24 +200,62 @@ pub(crate) async fn query(
             canceller.cancel();
         };
         monoio::spawn(cancel_io);
-        socket.cancelable_recv(buf, handle).await
+        let (result, buf) = socket
+            .cancelable_recv(Vec::with_capacity(capacity), handle)
+            .await;
+        result.map(|_| buf)
     }
+}
+
+fn bind(nameserver: SocketAddr) -> Result<UdpSocket, Error> {
+    let bind_address: SocketAddr = match nameserver.ip() {
+        std::net::IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        std::net::IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    Ok(UdpSocket::bind(bind_address)?)
+}
+
+/// Query a nameserver for the given question, using the UDP protocol.
+///
+/// The query uses the shared socket of its nameserver if there are `sockets`, unless its ID is already in flight there.
+/// Returns `None` if the UDP query failed and TCP should be used instead.
+pub(crate) async fn query(
+    query: &Query,
+    sockets: Option<&Sockets>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let Query {
+        id,
+        ref message,
+        ref nameserver,
+        attempts,
+        udp_payload_size,
+        ..
+    } = *query;
+
+    let registration = match sockets {
+        Some(sockets) => sockets.register(query)?,
+        None => None,
+    };
+    let socket = match registration {
+        Some(registration) => Socket::Shared(registration),
+        None => Socket::Own(bind(*nameserver)?),
+    };
 
     for idx in 1..=attempts {
-        let buf = Vec::with_capacity(udp_payload_size as usize);
-        let result = send(&socket, query.clone(), *nameserver).await;
+        let result = socket.send(message.clone(), *nameserver).await;
         if let Err(err) = result {
             if idx < attempts {
                 continue;
             }
             return Err(Error::from(err));
         }
-        let (result, buf) = recv(&socket, buf, timeout_duration).await;
-        if let Err(err) = result {
-            if idx < attempts {
-                continue;
-            }
-            return Err(Error::from(err));
+        let result = socket
+            .recv(udp_payload_size as usize, query.attempt_timeout(idx - 1))
+            .await;
+        let buf = match result {
+            Ok(buf) => buf,
+            Err(_) if idx < attempts => continue,
+            Err(err) => return Err(Error::from(err)),
         };
 
         let message = Message::from_octets(buf)?;
@@ -90,8 +271,7 @@ pub(crate) async fn query(
             return Ok(None);
         }
 
-        let ip_addresses = IpAddresses::from(message);
-        return Ok(Some(ip_addresses));
+        return Ok(Some(message.into_octets()));
     }
 
     // We did not receive a response.
@@ -0,0 +1,150 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr, SocketAddrV6},
+    rc::Rc,
+    time::Duration,
+};
+
+use crate::lookups::Transport;
+
+/// The protocol used to talk to a nameserver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Protocol {
+    /// UDP, falling back to TCP on truncation.
+    #[default]
+    Udp,
+    /// TCP only.
+    Tcp,
+    /// DNS over TLS (RFC 7858), requires a custom [`Transport`].
+    Tls,
+    /// DNS over HTTPS (RFC 8484), requires a custom [`Transport`].
+    Https,
+}
+
+impl Protocol {
+    /// The well-known port of the protocol.
+    pub const fn default_port(self) -> u16 {
+        match self {
+            Protocol::Udp | Protocol::Tcp => 53,
+            Protocol::Tls => 853,
+            Protocol::Https => 443,
+        }
+    }
+}
+
+/// An upstream nameserver, and how to query it.
+#[derive(Clone)]
+pub struct Nameserver {
+    pub addr: IpAddr,
+    pub port: u16,
+    /// The interface of a link-local IPv6 address.
+    pub scope_id: u32,
+    pub protocol: Protocol,
+    /// The name to verify the server certificate against, for [`Protocol::Tls`] and [`Protocol::Https`].
+    pub tls_name: Option<String>,
+    /// Overrides the resolver-wide timeout for this nameserver.
+    pub timeout: Option<Duration>,
+    /// Relative preference among nameservers, higher is preferred.
+    pub weight: u16,
+    pub enabled: bool,
+    /// Overrides the built-in transport of the protocol.
+    pub transport: Option<Rc<dyn Transport>>,
+}
+
+impl Nameserver {
+    pub fn new(addr: IpAddr) -> Self {
+        Self::with_protocol(addr, Protocol::Udp)
+    }
+
+    pub fn with_protocol(addr: IpAddr, protocol: Protocol) -> Self {
+        Self {
+            addr,
+            port: protocol.default_port(),
+            scope_id: 0,
+            protocol,
+            tls_name: None,
+            timeout: None,
+            weight: 1,
+            enabled: true,
+            transport: None,
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn scope_id(mut self, scope_id: u32) -> Self {
+        self.scope_id = scope_id;
+        self
+    }
+
+    pub fn tls_name(mut self, name: impl Into<String>) -> Self {
+        self.tls_name = Some(name.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Rc::new(transport));
+        self
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        match self.addr {
+            IpAddr::V6(addr) => SocketAddrV6::new(addr, self.port, 0, self.scope_id).into(),
+            addr => SocketAddr::new(addr, self.port),
+        }
+    }
+}
+
+impl From<SocketAddr> for Nameserver {
+    fn from(addr: SocketAddr) -> Self {
+        let nameserver = Self::new(addr.ip()).port(addr.port());
+        match addr {
+            SocketAddr::V6(addr) => nameserver.scope_id(addr.scope_id()),
+            SocketAddr::V4(_) => nameserver,
+        }
+    }
+}
+
+impl fmt::Debug for Nameserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nameserver")
+            .field("addr", &self.addr)
+            .field("port", &self.port)
+            .field("scope_id", &self.scope_id)
+            .field("protocol", &self.protocol)
+            .field("tls_name", &self.tls_name)
+            .field("timeout", &self.timeout)
+            .field("weight", &self.weight)
+            .field("enabled", &self.enabled)
+            .field("transport", &self.transport.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,290 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use bstr::ByteSlice;
+
+use crate::errors::Error;
+
+const HOSTS: &[u8] = "hosts:".as_bytes();
+
+/// A service of the `hosts` database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Service {
+    /// `/etc/hosts`.
+    Files,
+    /// The nameservers.
+    Dns,
+    /// The local hostname, like `nss-myhostname`.
+    Myhostname,
+    /// A service that is not supported, which is skipped.
+    Other(String),
+}
+
+impl Service {
+    fn parse(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "files" => Service::Files,
+            "dns" => Service::Dns,
+            "myhostname" => Service::Myhostname,
+            _ => Service::Other(name.to_owned()),
+        }
+    }
+}
+
+/// The outcome of a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The name was found.
+    Success,
+    /// The name does not exist.
+    NotFound,
+    /// The service is not available, e.g. no nameserver has answered.
+    Unavail,
+    /// The service is temporarily not available.
+    TryAgain,
+}
+
+impl Status {
+    const ALL: [Status; 4] = [
+        Status::Success,
+        Status::NotFound,
+        Status::Unavail,
+        Status::TryAgain,
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SUCCESS" => Some(Status::Success),
+            "NOTFOUND" => Some(Status::NotFound),
+            "UNAVAIL" => Some(Status::Unavail),
+            "TRYAGAIN" => Some(Status::TryAgain),
+            _ => None,
+        }
+    }
+}
+
+/// What to do after a service has completed with a status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Stop, with the outcome of the service.
+    Return,
+    /// Try the next service.
+    Continue,
+}
+
+impl Action {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "return" => Some(Action::Return),
+            // Merging only applies to the group databases.
+            "continue" | "merge" => Some(Action::Continue),
+            _ => None,
+        }
+    }
+}
+
+/// A service of the `hosts` database, along with its actions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NssEntry {
+    pub service: Service,
+    /// The actions, in the order of [`Status`].
+    actions: [Action; 4],
+}
+
+impl NssEntry {
+    /// Returns on success, and continues otherwise.
+    pub fn new(service: Service) -> Self {
+        Self {
+            service,
+            actions: [
+                Action::Return,
+                Action::Continue,
+                Action::Continue,
+                Action::Continue,
+            ],
+        }
+    }
+
+    pub fn action(&self, status: Status) -> Action {
+        self.actions[status as usize]
+    }
+
+    pub fn on(mut self, status: Status, action: Action) -> Self {
+        self.actions[status as usize] = action;
+        self
+    }
+
+    /// Applies the criteria between brackets, e.g. `!UNAVAIL=return`.
+    fn apply(&mut self, criteria: &str) {
+        let mut words = criteria
+            .split(|c: char| c.is_whitespace() || c == '=')
+            .filter(|word| !word.is_empty());
+        while let (Some(status), Some(action)) = (words.next(), words.next()) {
+            let (negated, status) = match status.strip_prefix('!') {
+                Some(status) => (true, status),
+                None => (false, status),
+            };
+            let (Some(status), Some(action)) = (Status::parse(status), Action::parse(action))
+            else {
+                continue;
+            };
+            for other in Status::ALL {
+                if (other == status) != negated {
+                    self.actions[other as usize] = action;
+                }
+            }
+        }
+    }
+}
+
+/// The order in which the services are tried to look up the hosts, from `/etc/nsswitch.conf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NsSwitch {
+    entries: Vec<NssEntry>,
+}
+
+impl Default for NsSwitch {
+    /// `files dns`.
+    fn default() -> Self {
+        Self::empty()
+            .entry(NssEntry::new(Service::Files))
+            .entry(NssEntry::new(Service::Dns))
+    }
+}
+
+impl NsSwitch {
+    /// No services.
+    pub fn empty() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn entry(mut self, entry: NssEntry) -> Self {
+        self.entries.push(entry);
+        self
+    }
+
+    pub fn entries(&self) -> &[NssEntry] {
+        &self.entries
+    }
+
+    /// The services that are not supported, and are skipped.
+    pub fn unsupported(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.service {
+                Service::Other(name) => Some(name.as_str()),
+                _ => None,
+            })
+    }
+
+    /// Parses the services of a `hosts` line, e.g. `files [NOTFOUND=return] dns`.
+    pub fn parse_hosts(line: &str) -> Self {
+        let mut this = Self::empty();
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            if let Some(criteria) = rest.strip_prefix('[') {
+                let (criteria, tail) = criteria.split_once(']').unwrap_or((criteria, ""));
+                if let Some(entry) = this.entries.last_mut() {
+                    entry.apply(criteria);
+                }
+                rest = tail.trim_start();
+            } else {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '[')
+                    .unwrap_or(rest.len());
+                let (service, tail) = rest.split_at(end);
+                this.entries.push(NssEntry::new(Service::parse(service)));
+                rest = tail.trim_start();
+            }
+        }
+        this
+    }
+
+    /// Parses the `hosts` line of `/etc/nsswitch.conf`, or the default if there is none.
+    pub fn from_nsswitch_conf(content: &[u8]) -> Result<Self, Error> {
+        for line in content.lines() {
+            let line = line.split_str("#").next().unwrap_or_default().trim();
+            if let Some(services) = line.strip_prefix(HOSTS) {
+                return Ok(Self::parse_hosts(services.to_str()?));
+            }
+        }
+        Ok(Self::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notfound_return() {
+        let nsswitch = NsSwitch::parse_hosts("files [NOTFOUND=return] dns");
+        let expected = NsSwitch::empty()
+            .entry(NssEntry::new(Service::Files).on(Status::NotFound, Action::Return))
+            .entry(NssEntry::new(Service::Dns));
+        assert_eq!(nsswitch, expected);
+        let files = &nsswitch.entries()[0];
+        assert_eq!(files.action(Status::Success), Action::Return);
+        assert_eq!(files.action(Status::NotFound), Action::Return);
+        assert_eq!(files.action(Status::Unavail), Action::Continue);
+        assert_eq!(files.action(Status::TryAgain), Action::Continue);
+    }
+
+    #[test]
+    fn negated_criteria() {
+        let nsswitch = NsSwitch::parse_hosts("dns [!UNAVAIL=return]files");
+        let dns = &nsswitch.entries()[0];
+        assert_eq!(dns.action(Status::Success), Action::Return);
+        assert_eq!(dns.action(Status::NotFound), Action::Return);
+        assert_eq!(dns.action(Status::Unavail), Action::Continue);
+        assert_eq!(dns.action(Status::TryAgain), Action::Return);
+        assert_eq!(nsswitch.entries()[1].service, Service::Files);
+    }
+
+    #[test]
+    fn several_criteria() {
+        let nsswitch = NsSwitch::parse_hosts("files [ success=continue  notfound = return ] dns");
+        let files = &nsswitch.entries()[0];
+        assert_eq!(files.action(Status::Success), Action::Continue);
+        assert_eq!(files.action(Status::NotFound), Action::Return);
+    }
+
+    #[test]
+    fn unknown_criteria() {
+        let nsswitch =
+            NsSwitch::parse_hosts("[NOTFOUND=return] files [BOGUS=return NOTFOUND=jump] dns");
+        assert_eq!(nsswitch, NsSwitch::default());
+    }
+
+    #[test]
+    fn unsupported_services() {
+        let nsswitch =
+            NsSwitch::parse_hosts("files mdns4_minimal [NOTFOUND=return] dns myhostname");
+        assert_eq!(
+            nsswitch.unsupported().collect::<Vec<_>>(),
+            ["mdns4_minimal"]
+        );
+        assert_eq!(nsswitch.entries()[3].service, Service::Myhostname);
+    }
+
+    #[test]
+    fn from_nsswitch_conf() {
+        let content = b"passwd: files\n# hosts: dns\nhosts:  files [NOTFOUND=return] # dns\n";
+        let nsswitch = NsSwitch::from_nsswitch_conf(content).unwrap();
+        let expected = NsSwitch::empty()
+            .entry(NssEntry::new(Service::Files).on(Status::NotFound, Action::Return));
+        assert_eq!(nsswitch, expected);
+
+        let nsswitch = NsSwitch::from_nsswitch_conf(b"passwd: files\n").unwrap();
+        assert_eq!(nsswitch, NsSwitch::default());
+    }
+}
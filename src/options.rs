@@ -0,0 +1,279 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    cell::{Cell, RefCell},
+    future::{poll_fn, Future},
+    net::SocketAddr,
+    pin::pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{errors::Error, lookups::udp::Sockets, Config, Protocol};
+
+/// The address families to look up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    pub(crate) fn includes_ipv4(self) -> bool {
+        self != AddressFamily::Ipv6
+    }
+
+    pub(crate) fn includes_ipv6(self) -> bool {
+        self != AddressFamily::Ipv4
+    }
+}
+
+/// How the search domains are applied to the name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Search {
+    /// Like glibc, according to `ndots`.
+    #[default]
+    Auto,
+    /// Only look up the name as is.
+    Disabled,
+    /// Only look up the name with the search domains appended.
+    Only,
+}
+
+/// Options of a single lookup, see [`crate::StubResolver::lookup_with`].
+#[derive(Debug, Clone)]
+pub struct LookupOptions {
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) attempts: Option<u8>,
+    pub(crate) family: AddressFamily,
+    pub(crate) search: Search,
+    pub(crate) hosts: bool,
+    pub(crate) recursion_desired: bool,
+    pub(crate) dnssec_ok: bool,
+    pub(crate) checking_disabled: bool,
+    pub(crate) protocol: Option<Protocol>,
+}
+
+impl Default for LookupOptions {
+    fn default() -> Self {
+        Self {
+            deadline: None,
+            cancellation: None,
+            timeout: None,
+            attempts: None,
+            family: AddressFamily::default(),
+            search: Search::default(),
+            hosts: true,
+            recursion_desired: true,
+            dnssec_ok: false,
+            checking_disabled: false,
+            protocol: None,
+        }
+    }
+}
+
+impl LookupOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the timeout of the nameservers.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn attempts(mut self, attempts: u8) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    pub fn family(mut self, family: AddressFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    pub fn search(mut self, search: Search) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Whether to consult `/etc/hosts`, enabled by default.
+    pub fn hosts(mut self, enabled: bool) -> Self {
+        self.hosts = enabled;
+        self
+    }
+
+    /// The RD bit, set by default.
+    pub fn recursion_desired(mut self, enabled: bool) -> Self {
+        self.recursion_desired = enabled;
+        self
+    }
+
+    /// The DO bit of the EDNS(0) header, asking for DNSSEC records.
+    pub fn dnssec_ok(mut self, enabled: bool) -> Self {
+        self.dnssec_ok = enabled;
+        self
+    }
+
+    /// The CD bit, asking the nameserver not to validate DNSSEC.
+    pub fn checking_disabled(mut self, enabled: bool) -> Self {
+        self.checking_disabled = enabled;
+        self
+    }
+
+    /// Prefers a protocol: [`Protocol::Udp`] and [`Protocol::Tcp`] change how plain nameservers are queried,
+    /// while [`Protocol::Tls`] and [`Protocol::Https`] restrict the lookup to the nameservers of that protocol.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Bounds the total time of the lookup, overriding [`crate::StubResolverBuilder::deadline`].
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Aborts the lookup, and all of its in-flight queries, once the token is cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+}
+
+/// A handle to cancel one or more lookups.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Rc<CancellationState>);
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: Cell<bool>,
+    wakers: RefCell<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.cancelled.set(true);
+        for waker in self.0.wakers.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.get()
+    }
+
+    fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.0.wakers.borrow_mut();
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// How far a lookup went before it was aborted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The names that were queried, after the search domains were applied.
+    pub names: Vec<String>,
+    /// The nameservers that were queried.
+    pub nameservers: Vec<SocketAddr>,
+    pub elapsed: Duration,
+}
+
+/// The state of an ongoing lookup.
+#[derive(Debug)]
+pub(crate) struct LookupContext {
+    pub(crate) options: LookupOptions,
+    /// The configuration of the resolver when the lookup started.
+    pub(crate) config: Rc<Config>,
+    /// The UDP sockets of the batch of the lookup, if any.
+    pub(crate) sockets: Option<Sockets>,
+    started: Instant,
+    progress: RefCell<Progress>,
+}
+
+impl LookupContext {
+    pub(crate) fn new(options: LookupOptions, config: Rc<Config>) -> Self {
+        Self {
+            options,
+            config,
+            sockets: None,
+            started: Instant::now(),
+            progress: RefCell::default(),
+        }
+    }
+
+    pub(crate) fn querying_name(&self, name: &str) {
+        self.progress.borrow_mut().names.push(name.to_owned());
+    }
+
+    pub(crate) fn querying_nameserver(&self, nameserver: SocketAddr) {
+        let mut progress = self.progress.borrow_mut();
+        if !progress.nameservers.contains(&nameserver) {
+            progress.nameservers.push(nameserver);
+        }
+    }
+
+    fn progress(&self) -> Progress {
+        let mut progress = self.progress.borrow().clone();
+        progress.elapsed = self.started.elapsed();
+        progress
+    }
+
+    /// Runs the lookup until it completes, the deadline elapses or the lookup is cancelled.
+    ///
+    /// An aborted lookup is dropped, which cancels its in-flight queries.
+    pub(crate) async fn run<T>(
+        &self,
+        deadline: Option<Duration>,
+        lookup: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let mut lookup = pin!(lookup);
+        let mut timer = deadline.map(|deadline| Box::pin(monoio::time::sleep(deadline)));
+        let cancellation = self.options.cancellation.clone();
+        poll_fn(|cx| {
+            if let Poll::Ready(result) = lookup.as_mut().poll(cx) {
+                return Poll::Ready(result);
+            }
+            if let Some(timer) = timer.as_mut() {
+                if timer.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(Error::Timeout {
+                        progress: self.progress(),
+                    }));
+                }
+            }
+            if let Some(cancellation) = &cancellation {
+                if cancellation.poll_cancelled(cx).is_ready() {
+                    return Poll::Ready(Err(Error::Cancelled {
+                        progress: self.progress(),
+                    }));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
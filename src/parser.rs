@@ -8,16 +8,45 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 //
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use bstr::ByteSlice;
-use monoio::fs::read;
+use monoio::fs::{metadata, read};
 
 use crate::errors::Error;
-use crate::{HostEntry, StubResolver};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use crate::resolvers::{Hosts, Routes};
+use crate::{
+    Config, HostEntry, Nameserver, NsSwitch, PolicyTable, StubResolver, StubResolverBuilder,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+const HOSTS: &str = "/etc/hosts";
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const GAI_CONF: &str = "/etc/gai.conf";
+const LOCALDOMAIN: &str = "LOCALDOMAIN";
+const RES_OPTIONS: &str = "RES_OPTIONS";
+const HOSTALIASES: &str = "HOSTALIASES";
+const SYSTEMD_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
+const SYSTEMD_LINKS: &str = "/run/systemd/resolve/netif";
+const SYSTEMD_STUBS: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 53)),
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 54)),
+];
+const LINK_SERVERS: &[u8] = "SERVERS=".as_bytes();
+const LINK_DOMAINS: &[u8] = "DOMAINS=".as_bytes();
+const LINK_DEFAULT_ROUTE: &[u8] = "DEFAULT_ROUTE=".as_bytes();
+const PORT: &[u8] = "port".as_bytes();
+const TIMEOUT: &[u8] = "timeout".as_bytes();
+const NSSWITCH_CONF: &str = "/etc/nsswitch.conf";
+const HOSTNAME: &str = "/proc/sys/kernel/hostname";
 const NAMESERVER: &[u8] = "nameserver".as_bytes();
 const OPTIONS: &[u8] = "options".as_bytes();
 const OPTION_NDOTS: &[u8] = "ndots".as_bytes();
@@ -26,22 +55,30 @@ const OPTION_ATTEMPTS: &[u8] = "attempts".as_bytes();
 const OPTION_ROTATE: &[u8] = "rotate".as_bytes();
 const OPTION_EDNS0: &[u8] = "edns0".as_bytes();
 const SEARCH: &[u8] = "search".as_bytes();
-const DEFAULT_NAMESERVER_IPV4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
-const DEFAULT_NAMESERVER_IPV6: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 53);
+const DOMAIN: &[u8] = "domain".as_bytes();
+const SORTLIST: &[u8] = "sortlist".as_bytes();
+/// Like glibc.
+const MAX_SORTLIST: usize = 10;
+/// The longest timeout of a resolver file, like the `timeout` option of glibc.
+const MAX_TIMEOUT: u64 = 30;
+const DEFAULT_NAMESERVER_IPV4: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_NAMESERVER_IPV6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
 
-impl StubResolver {
+impl Config {
     async fn parse_hosts(&mut self) -> Result<(), Error> {
-        let content = read("/etc/hosts").await?;
+        let content = read(HOSTS).await?;
         for line in content.lines() {
             let mut it = line.fields().take_while(|field| !field.starts_with(b"#"));
             if let Some(ip) = it.next() {
                 let ip = ip.to_str()?;
                 let ip = IpAddr::from_str(ip)?;
-                let hosts = it
+                let mut hosts = it
                     .map(|host| String::from_utf8(host.to_owned()))
                     .filter_map(|host| host.ok());
-                let entry = HostEntry::new(ip, hosts);
-                self.entries.push(entry);
+                // Like glibc, lines without names are ignored.
+                if let Some(canonical) = hosts.next() {
+                    self.hosts.push(HostEntry::new(ip, canonical, hosts));
+                }
             } else {
                 continue;
             };
@@ -49,8 +86,13 @@ impl StubResolver {
         Ok(())
     }
 
-    async fn parse_resolv(&mut self) -> Result<(), Error> {
-        let content = read("/etc/resolv.conf").await?;
+    async fn parse_resolv(&mut self, path: &str) -> Result<(), Error> {
+        let content = read(path).await?;
+        self.parse_resolv_conf(&content)
+    }
+
+    /// Parses the content of a `resolv.conf`, on top of the current configuration.
+    fn parse_resolv_conf(&mut self, content: &[u8]) -> Result<(), Error> {
         for line in content.lines() {
             let mut it = line.fields().take_while(|field| !field.starts_with(b"#"));
             match it.next() {
@@ -58,60 +100,771 @@ impl StubResolver {
                     let it = it.filter_map(|x| x.to_str().ok()).map(|x| x.to_owned());
                     self.search = it.collect();
                 }
-                Some(NAMESERVER) => {
-                    if let Some(ip) = it.next() {
-                        let ip = ip.to_str()?;
-                        let ip = IpAddr::from_str(ip)?;
-                        let addr = SocketAddr::new(ip, 53);
-                        self.nameservers.push(addr);
+                // The last of `domain` and `search` wins.
+                Some(DOMAIN) => {
+                    if let Some(domain) = it.next() {
+                        self.search = vec![domain.to_str()?.to_owned()];
                     }
                 }
-                Some(OPTIONS) => {
-                    for field in it {
-                        if OPTION_EDNS0 == field {
-                            self.udp_payload_size = 1232;
-                        } else if let Some((key, value)) = field.split_once_str(":") {
-                            match key {
-                                OPTION_NDOTS => self.ndots = value[0],
-                                OPTION_TIMEOUT => {
-                                    self.timeout = Duration::from_secs(value[0] as u64)
-                                }
-                                OPTION_ATTEMPTS => self.attempts = value[0],
-                                OPTION_ROTATE => self.rotate = true,
-                                _ => {}
-                            }
+                Some(NAMESERVER) => {
+                    // Like glibc, the extra nameservers are ignored.
+                    if let Some(ip) = it.next() {
+                        if self.nameservers.len() < self.builder.max_nameservers {
+                            let ip = ip.to_str()?;
+                            let ip = IpAddr::from_str(ip)?;
+                            self.nameservers.push(Nameserver::new(ip));
                         }
                     }
                 }
+                Some(SORTLIST) => {
+                    let it = it.filter_map(parse_sortlist_entry).take(MAX_SORTLIST);
+                    self.sortlist = it.collect();
+                }
+                Some(OPTIONS) => self.parse_options(it),
                 _ => {}
             }
         }
-        if self.nameservers.is_empty() {
-            self.nameservers.push(DEFAULT_NAMESERVER_IPV4);
-            self.nameservers.push(DEFAULT_NAMESERVER_IPV6);
+        // Like glibc, the extra search domains are ignored.
+        self.search.truncate(self.builder.max_search_domains);
+        Ok(())
+    }
+
+    fn parse_options<'a>(&mut self, fields: impl Iterator<Item = &'a [u8]>) {
+        // Values that are out of range saturate.
+        let number = |value: &[u8]| {
+            let value = value.to_str().ok()?.parse::<u64>().ok()?;
+            Some(value.min(u8::MAX.into()) as u8)
+        };
+        for field in fields {
+            if OPTION_EDNS0 == field {
+                self.udp_payload_size = 1232;
+            } else if OPTION_ROTATE == field {
+                self.rotate = true;
+            } else if let Some((key, value)) = field.split_once_str(":") {
+                let Some(value) = number(value) else {
+                    continue;
+                };
+                match key {
+                    OPTION_NDOTS => self.ndots = value,
+                    OPTION_TIMEOUT => self.timeout = Duration::from_secs(value.into()),
+                    OPTION_ATTEMPTS => self.attempts = value,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Applies `LOCALDOMAIN` and `RES_OPTIONS` on top of `/etc/resolv.conf`, and reads `HOSTALIASES`.
+    async fn parse_environment(&mut self) -> Result<(), Error> {
+        if let Some(domains) = std::env::var_os(LOCALDOMAIN) {
+            let domains = domains.as_encoded_bytes().fields();
+            self.search = domains
+                .filter_map(|domain| domain.to_str().ok())
+                .map(|domain| domain.to_owned())
+                .collect();
+            self.search.truncate(self.builder.max_search_domains);
+        }
+        if let Some(options) = std::env::var_os(RES_OPTIONS) {
+            self.parse_options(options.as_encoded_bytes().fields());
+        }
+        if let Some(path) = std::env::var_os(HOSTALIASES) {
+            let content = match read(&path).await {
+                Ok(content) => content,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(err) => return Err(err.into()),
+            };
+            for line in content.lines() {
+                let mut it = line.fields().take_while(|field| !field.starts_with(b"#"));
+                let (Some(alias), Some(name)) = (it.next(), it.next()) else {
+                    continue;
+                };
+                let (Ok(alias), Ok(name)) = (alias.to_str(), name.to_str()) else {
+                    continue;
+                };
+                // Like glibc, the first alias wins.
+                self.host_aliases
+                    .entry(alias.to_ascii_lowercase())
+                    .or_insert_with(|| name.trim_end_matches('.').to_owned());
+            }
         }
         Ok(())
     }
 
+    async fn parse_gai_conf(&mut self) -> Result<(), Error> {
+        let content = match read(GAI_CONF).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        self.policy_table = Some(PolicyTable::from_gai_conf(&content)?);
+        Ok(())
+    }
+
+    async fn parse_nsswitch_conf(&mut self) -> Result<(), Error> {
+        let content = match read(NSSWITCH_CONF).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        self.nsswitch = NsSwitch::from_nsswitch_conf(&content)?;
+        Ok(())
+    }
+
+    async fn read_hostname(&mut self) {
+        let hostname = read(HOSTNAME).await.ok();
+        self.hostname = hostname
+            .and_then(|hostname| String::from_utf8(hostname).ok())
+            .map(|hostname| hostname.trim().to_owned())
+            .filter(|hostname| !hostname.is_empty());
+    }
+}
+
+impl StubResolver {
+    pub fn builder() -> StubResolverBuilder {
+        StubResolverBuilder::default()
+    }
+
     pub async fn load() -> Result<Self, Error> {
-        let mut this = Self {
-            entries: Vec::default(),
+        Self::builder().build().await
+    }
+
+    pub(crate) async fn from_builder(builder: StubResolverBuilder) -> Result<Self, Error> {
+        let strategy = builder.strategy;
+        let deadline = builder.deadline;
+        let special_use = builder.special_use;
+        let config = Config::from_builder(builder).await?;
+        Ok(Self {
+            config: RefCell::new(Rc::new(config)),
+            strategy,
+            deadline,
+            special_use,
+            stats: Rc::default(),
+            lookups: Default::default(),
+            queries: Default::default(),
+        })
+    }
+
+    /// Reloads the system configuration files that have changed since they were last read.
+    ///
+    /// The files are compared by their inode, size and modification time. The parts of the configuration
+    /// that come from unchanged files are kept as they are, as well as the health of the nameservers.
+    /// The lookups in flight carry on with the configuration they started with.
+    pub async fn reload(&self) -> Result<Reloaded, Error> {
+        let current = self.config();
+        let builder = &current.builder;
+        let files = &current.files;
+        let mut reloaded = Reloaded::default();
+        let system_config = builder.system_config;
+        // The changed files are loaded aside, so that the configuration is left untouched on failure.
+        let mut fresh = Config::new(builder.clone());
+        reloaded.hosts = system_config && FileStamp::of(HOSTS).await? != files.hosts;
+        if reloaded.hosts {
+            fresh.load_hosts().await?;
+        }
+        reloaded.resolv_conf = system_config
+            && (FileStamp::of(RESOLV_CONF).await? != files.resolv
+                || builder.systemd_resolved != SystemdResolved::Disabled
+                    && (FileStamp::of(SYSTEMD_RESOLV_CONF).await? != files.systemd_resolv
+                        || FileStamp::of(SYSTEMD_LINKS).await? != files.systemd_links))
+            // The resolver directory is read even without the system configuration.
+            || match &builder.resolver_dir {
+                Some(dir) => DirStamp::of(dir).await? != files.resolver_dir,
+                None => false,
+            };
+        if reloaded.resolv_conf {
+            fresh.load_resolv().await?;
+        }
+        reloaded.gai_conf = system_config
+            && builder.sort_addresses
+            && builder.policy_table.is_none()
+            && builder.gai_conf
+            && FileStamp::of(GAI_CONF).await? != files.gai;
+        if reloaded.gai_conf {
+            fresh.load_policy_table().await?;
+        }
+        reloaded.nsswitch_conf = system_config
+            && builder.nsswitch.is_none()
+            && FileStamp::of(NSSWITCH_CONF).await? != files.nsswitch;
+        if reloaded.nsswitch_conf {
+            fresh.load_nsswitch().await?;
+        }
+
+        // The ongoing lookups keep the configuration they started with.
+        let mut config = Config::clone(&current);
+        if reloaded.hosts {
+            config.files.hosts = fresh.files.hosts;
+            config.hosts = fresh.hosts;
+        }
+        if reloaded.resolv_conf {
+            config.files.resolv = fresh.files.resolv;
+            config.files.systemd_resolv = fresh.files.systemd_resolv;
+            config.files.systemd_links = fresh.files.systemd_links;
+            config.files.resolver_dir = fresh.files.resolver_dir;
+            config.search = fresh.search;
+            config.sortlist = fresh.sortlist;
+            config.host_aliases = fresh.host_aliases;
+            config.nameservers = fresh.nameservers;
+            config.routes = fresh.routes;
+            config.timeout = fresh.timeout;
+            config.ndots = fresh.ndots;
+            config.attempts = fresh.attempts;
+            config.rotate = fresh.rotate;
+            config.udp_payload_size = fresh.udp_payload_size;
+        }
+        if reloaded.gai_conf {
+            config.files.gai = fresh.files.gai;
+            config.policy_table = fresh.policy_table;
+        }
+        if reloaded.nsswitch_conf {
+            config.files.nsswitch = fresh.files.nsswitch;
+            config.nsswitch = fresh.nsswitch;
+        }
+        config.read_hostname().await;
+        *self.config.borrow_mut() = Rc::new(config);
+        Ok(reloaded)
+    }
+}
+
+impl Config {
+    /// An unconfigured resolver, with the defaults of glibc.
+    fn new(builder: StubResolverBuilder) -> Self {
+        Self {
+            hosts: Hosts::default(),
             search: Vec::default(),
+            sortlist: Vec::default(),
+            host_aliases: HashMap::default(),
             nameservers: Vec::default(),
+            routes: Routes::default(),
             ndots: 1,
             timeout: Duration::from_secs(5),
             attempts: 2,
             rotate: false,
             udp_payload_size: 512,
-        };
-        this.parse_hosts().await?;
-        this.parse_resolv().await?;
+            policy_table: None,
+            nsswitch: NsSwitch::default(),
+            hostname: None,
+            builder,
+            files: Files::default(),
+        }
+    }
+
+    async fn from_builder(builder: StubResolverBuilder) -> Result<Self, Error> {
+        let mut this = Self::new(builder);
+        this.load_hosts().await?;
+        this.load_resolv().await?;
+        this.load_policy_table().await?;
+        this.load_nsswitch().await?;
+        this.read_hostname().await;
         Ok(this)
     }
 
-    pub async fn reload(&mut self) -> Result<(), Error> {
-        let this = Self::load().await?;
-        *self = this;
+    async fn load_hosts(&mut self) -> Result<(), Error> {
+        if self.builder.system_config {
+            self.files.hosts = FileStamp::of(HOSTS).await?;
+            self.parse_hosts().await?;
+        }
+        Ok(())
+    }
+
+    async fn load_resolv(&mut self) -> Result<(), Error> {
+        if self.builder.system_config {
+            self.files.resolv = FileStamp::of(RESOLV_CONF).await?;
+            self.parse_resolv(RESOLV_CONF).await?;
+            if self.builder.systemd_resolved != SystemdResolved::Disabled {
+                self.load_systemd_resolved().await?;
+            }
+        }
+        if self.builder.environment {
+            self.parse_environment().await?;
+        }
+        if let Some(dir) = self.builder.resolver_dir.clone() {
+            self.files.resolver_dir = DirStamp::of(&dir).await?;
+            self.parse_resolver_dir(&dir).await?;
+        }
+
+        let builder = &self.builder;
+        if !builder.nameservers.is_empty() {
+            self.nameservers = builder.nameservers.clone();
+        }
+        for (domain, nameservers) in &builder.routes {
+            self.routes.add(domain, nameservers.clone());
+        }
+        if let Some(search) = &builder.search {
+            self.search = search.clone();
+        }
+        self.timeout = builder.timeout.unwrap_or(self.timeout);
+        self.ndots = builder.ndots.unwrap_or(self.ndots);
+        self.attempts = builder.attempts.unwrap_or(self.attempts);
+        self.rotate = builder.rotate.unwrap_or(self.rotate);
+        self.udp_payload_size = builder.udp_payload_size.unwrap_or(self.udp_payload_size);
+
+        if self.nameservers.is_empty() {
+            self.nameservers
+                .push(Nameserver::new(DEFAULT_NAMESERVER_IPV4));
+            self.nameservers
+                .push(Nameserver::new(DEFAULT_NAMESERVER_IPV6));
+        }
+        Ok(())
+    }
+
+    /// Replaces the stub of systemd-resolved with its upstream nameservers, if it is the only nameserver.
+    async fn load_systemd_resolved(&mut self) -> Result<(), Error> {
+        self.files.systemd_resolv = FileStamp::of(SYSTEMD_RESOLV_CONF).await?;
+        self.files.systemd_links = FileStamp::of(SYSTEMD_LINKS).await?;
+        let is_stub = |ns: &Nameserver| SYSTEMD_STUBS.contains(&ns.addr) && ns.port == 53;
+        if self.nameservers.is_empty() || !self.nameservers.iter().all(is_stub) {
+            return Ok(());
+        }
+
+        // The stub is kept if the upstream nameservers are not available.
+        let stub = std::mem::take(&mut self.nameservers);
+        if self.builder.systemd_resolved == SystemdResolved::Links {
+            self.parse_systemd_links().await?;
+        }
+        if self.nameservers.is_empty() && self.files.systemd_resolv.exists() {
+            self.parse_resolv(SYSTEMD_RESOLV_CONF).await?;
+        }
+        if self.nameservers.is_empty() {
+            self.nameservers = stub;
+        }
         Ok(())
     }
+
+    /// Reads the nameservers of the links managed by systemd-resolved, and routes their domains to them.
+    ///
+    /// Like systemd-resolved, only the links that are a default route are used for the other names:
+    /// those with `DEFAULT_ROUTE=yes`, the root (`~.`) domain, or no routing-only domains when it is not set.
+    ///
+    /// The search domains of the stub are kept, as they include the global ones, which the links do not.
+    async fn parse_systemd_links(&mut self) -> Result<(), Error> {
+        let mut links: Vec<(u32, PathBuf)> = match std::fs::read_dir(SYSTEMD_LINKS) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let index = entry.file_name().to_str()?.parse().ok()?;
+                    Some((index, entry.path()))
+                })
+                .collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        links.sort();
+
+        for (index, path) in links {
+            let content = match read(&path).await {
+                Ok(content) => content,
+                // The link may have gone away meanwhile.
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let mut servers = Vec::new();
+            let mut domains = Vec::new();
+            let mut default_route = None;
+            for line in content.lines() {
+                if let Some(fields) = line.strip_prefix(LINK_SERVERS) {
+                    let fields = fields.fields();
+                    servers.extend(fields.filter_map(|field| parse_link_server(field, index)));
+                } else if let Some(fields) = line.strip_prefix(LINK_DOMAINS) {
+                    // Both the search and the routing-only (`~`) domains are routed to the link.
+                    domains.extend(fields.fields().filter_map(|domain| domain.to_str().ok()));
+                } else if let Some(value) = line.strip_prefix(LINK_DEFAULT_ROUTE) {
+                    default_route = Some(value.trim() == b"yes");
+                }
+            }
+            if servers.is_empty() {
+                continue;
+            }
+
+            let default_route = domains.contains(&"~.")
+                || default_route
+                    .unwrap_or_else(|| !domains.iter().any(|domain| domain.starts_with('~')));
+            if default_route {
+                for server in &servers {
+                    let exists = self
+                        .nameservers
+                        .iter()
+                        .any(|ns| ns.socket_addr() == server.socket_addr());
+                    if !exists && self.nameservers.len() < self.builder.max_nameservers {
+                        self.nameservers.push(server.clone());
+                    }
+                }
+            }
+            // The root marks a default route, which the nameservers above already are.
+            let domains = domains
+                .into_iter()
+                .map(|domain| domain.trim_start_matches('~'));
+            for domain in domains.filter(|domain| *domain != ".") {
+                self.routes.add(domain, servers.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the routes of a directory of files named after their domain, like `/etc/resolver` on macOS.
+    async fn parse_resolver_dir(&mut self, dir: &Path) -> Result<(), Error> {
+        let mut files: Vec<(String, PathBuf)> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                // Symlinks are followed, and skipped below if they do not point to a readable file.
+                .filter(|entry| {
+                    let file_type = entry.file_type();
+                    file_type.is_ok_and(|file_type| file_type.is_file() || file_type.is_symlink())
+                })
+                .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+                // Hidden files, e.g. the temporary files of editors.
+                .filter(|(domain, _)| !domain.starts_with('.'))
+                .collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        files.sort();
+
+        for (domain, path) in files {
+            // A file that cannot be read does not prevent the others from being used.
+            let Ok(content) = read(&path).await else {
+                continue;
+            };
+            let mut nameservers = Vec::new();
+            let mut port = None;
+            let mut timeout = None;
+            for line in content.lines() {
+                let mut it = line.fields().take_while(|field| !field.starts_with(b"#"));
+                let (Some(directive), Some(value)) = (it.next(), it.next()) else {
+                    continue;
+                };
+                let Ok(value) = value.to_str() else {
+                    continue;
+                };
+                match directive {
+                    NAMESERVER => {
+                        if let Ok(addr) = IpAddr::from_str(value) {
+                            nameservers.push(Nameserver::new(addr));
+                        }
+                    }
+                    PORT => port = value.parse().ok(),
+                    TIMEOUT => {
+                        let secs = value.parse::<u64>().ok();
+                        timeout = secs.map(|secs| Duration::from_secs(secs.min(MAX_TIMEOUT)));
+                    }
+                    _ => {}
+                }
+            }
+            for nameserver in nameservers.iter_mut() {
+                if let Some(port) = port {
+                    nameserver.port = port;
+                }
+                nameserver.timeout = timeout.or(nameserver.timeout);
+            }
+            if !nameservers.is_empty() {
+                self.routes.add(&domain, nameservers);
+            }
+        }
+        Ok(())
+    }
+
+    async fn load_policy_table(&mut self) -> Result<(), Error> {
+        if self.builder.sort_addresses {
+            if let Some(table) = &self.builder.policy_table {
+                self.policy_table = Some(table.clone());
+            } else if self.builder.gai_conf {
+                self.files.gai = FileStamp::of(GAI_CONF).await?;
+                self.parse_gai_conf().await?;
+            }
+            self.policy_table.get_or_insert_with(PolicyTable::default);
+        }
+        Ok(())
+    }
+
+    async fn load_nsswitch(&mut self) -> Result<(), Error> {
+        if let Some(nsswitch) = &self.builder.nsswitch {
+            self.nsswitch = nsswitch.clone();
+        } else if self.builder.system_config {
+            self.files.nsswitch = FileStamp::of(NSSWITCH_CONF).await?;
+            self.parse_nsswitch_conf().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a server of a systemd-resolved link, `address[%interface][:port][#name]`.
+fn parse_link_server(field: &[u8], ifindex: u32) -> Option<Nameserver> {
+    let field = field.to_str().ok()?;
+    let (addr, name) = match field.split_once('#') {
+        Some((addr, name)) => (addr, Some(name)),
+        None => (field, None),
+    };
+    // The scope follows the address or the port, e.g. `fe80::1%2`, `[fe80::1%2]:53` or `[fe80::1]:53%2`.
+    let (addr, scope) = match addr.split_once('%') {
+        Some((head, tail)) => {
+            let end = tail.find([']', ':']).unwrap_or(tail.len());
+            (format!("{head}{}", &tail[end..]), Some(&tail[..end]))
+        }
+        None => (addr.to_owned(), None),
+    };
+    let nameserver = match SocketAddr::from_str(&addr) {
+        Ok(addr) => Nameserver::from(addr),
+        Err(_) => Nameserver::new(IpAddr::from_str(&addr).ok()?),
+    };
+    let nameserver = match nameserver.addr {
+        // The interface of a link-local address is the link itself, unless given by its index.
+        IpAddr::V6(addr) if addr.segments()[0] & 0xffc0 == 0xfe80 => {
+            let scope_id = scope.and_then(|scope| scope.parse().ok());
+            nameserver.scope_id(scope_id.unwrap_or(ifindex))
+        }
+        _ => nameserver,
+    };
+    Some(match name {
+        Some(name) => nameserver.tls_name(name),
+        None => nameserver,
+    })
+}
+
+/// Parses an `address[/netmask]` pair of `sortlist`, the netmask defaults to the one of the address class.
+fn parse_sortlist_entry(field: &[u8]) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let (addr, mask) = match field.split_once_str("/") {
+        Some((addr, mask)) => (addr, Some(mask)),
+        None => (field, None),
+    };
+    let addr = Ipv4Addr::from_str(addr.to_str().ok()?).ok()?;
+    let mask = match mask {
+        Some(mask) => Ipv4Addr::from_str(mask.to_str().ok()?).ok()?,
+        None => match addr.octets()[0] {
+            0..=127 => Ipv4Addr::new(255, 0, 0, 0),
+            128..=191 => Ipv4Addr::new(255, 255, 0, 0),
+            _ => Ipv4Addr::new(255, 255, 255, 0),
+        },
+    };
+    Some((addr, mask))
+}
+
+/// The identity of a configuration file when it was read, `None` if it did not exist.
+///
+/// The metadata is queried through the runtime (`statx` on io_uring), so that checking for changes does not block it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct FileStamp(Option<(u64, u64, u64, SystemTime)>);
+
+impl FileStamp {
+    async fn of(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match metadata(path).await {
+            Ok(metadata) => Ok(Self(Some((
+                metadata.dev(),
+                metadata.ino(),
+                metadata.len(),
+                metadata.modified()?,
+            )))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self(None)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn exists(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// The identities of the files of a directory, by name, empty if it did not exist.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DirStamp(Vec<(OsString, FileStamp)>);
+
+impl DirStamp {
+    async fn of(path: &Path) -> Result<Self, Error> {
+        // The runtime cannot list directories, which are small here.
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut stamps = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let stamp = FileStamp::of(entry.path()).await?;
+            stamps.push((entry.file_name(), stamp));
+        }
+        stamps.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Self(stamps))
+    }
+}
+
+/// The configuration files as they were last read.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Files {
+    hosts: FileStamp,
+    resolv: FileStamp,
+    systemd_resolv: FileStamp,
+    /// The directory of the links, whose files are replaced on every change.
+    systemd_links: FileStamp,
+    resolver_dir: DirStamp,
+    gai: FileStamp,
+    nsswitch: FileStamp,
+}
+
+/// Whether to bypass the stub of systemd-resolved, when it is the nameserver of `/etc/resolv.conf`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SystemdResolved {
+    /// Use the stub.
+    #[default]
+    Disabled,
+    /// Use the upstream nameservers of `/run/systemd/resolve/resolv.conf`.
+    Upstream,
+    /// Use the nameservers of the default-route links in `/run/systemd/resolve/netif`, in the order of the links,
+    /// or the upstream nameservers if the links have none. The other links only serve their domains.
+    Links,
+}
+
+/// The configuration files that were reloaded by [`StubResolver::reload`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct Reloaded {
+    pub hosts: bool,
+    /// `/etc/resolv.conf`, along with the state of systemd-resolved and the resolver directory.
+    pub resolv_conf: bool,
+    pub gai_conf: bool,
+    pub nsswitch_conf: bool,
+}
+
+impl Reloaded {
+    /// Whether any of the files was reloaded.
+    pub fn any(&self) -> bool {
+        self.hosts || self.resolv_conf || self.gai_conf || self.nsswitch_conf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sort::apply_sortlist;
+
+    fn parse(builder: StubResolverBuilder, content: &str) -> Config {
+        let mut resolver = Config::new(builder);
+        resolver.parse_resolv_conf(content.as_bytes()).unwrap();
+        resolver
+    }
+
+    fn nameservers(resolver: &Config) -> Vec<IpAddr> {
+        resolver.nameservers.iter().map(|ns| ns.addr).collect()
+    }
+
+    #[test]
+    fn sortlist_entry() {
+        let entry = |field: &str| parse_sortlist_entry(field.as_bytes());
+        assert_eq!(
+            entry("130.155.160.0/255.255.240.0"),
+            Some((
+                Ipv4Addr::new(130, 155, 160, 0),
+                Ipv4Addr::new(255, 255, 240, 0)
+            ))
+        );
+        // The netmask of the address class.
+        assert_eq!(
+            entry("10.1.2.3"),
+            Some((Ipv4Addr::new(10, 1, 2, 3), Ipv4Addr::new(255, 0, 0, 0)))
+        );
+        assert_eq!(
+            entry("172.16.0.0"),
+            Some((Ipv4Addr::new(172, 16, 0, 0), Ipv4Addr::new(255, 255, 0, 0)))
+        );
+        assert_eq!(
+            entry("192.168.1.0"),
+            Some((
+                Ipv4Addr::new(192, 168, 1, 0),
+                Ipv4Addr::new(255, 255, 255, 0)
+            ))
+        );
+        assert_eq!(entry("192.168.1.0/24"), None);
+        assert_eq!(entry("2001:db8::/32"), None);
+        assert_eq!(entry("example.com"), None);
+    }
+
+    #[test]
+    fn sortlist() {
+        let content = "sortlist 130.155.160.0/255.255.240.0 10.0.0.0 bogus\n";
+        let resolver = parse(StubResolverBuilder::default(), content);
+        assert_eq!(resolver.sortlist.len(), 2);
+
+        let ip = |ip: &str| (IpAddr::from_str(ip).unwrap(), Duration::ZERO);
+        let mut addrs = vec![
+            ip("192.0.2.1"),
+            ip("2001:db8::1"),
+            ip("10.9.8.7"),
+            ip("130.155.170.1"),
+            ip("130.155.150.1"),
+            ip("10.1.1.1"),
+        ];
+        apply_sortlist(&resolver.sortlist, &mut addrs);
+        let expected = [
+            ip("130.155.170.1"),
+            ip("10.9.8.7"),
+            ip("10.1.1.1"),
+            ip("192.0.2.1"),
+            ip("2001:db8::1"),
+            ip("130.155.150.1"),
+        ];
+        assert_eq!(addrs, expected);
+    }
+
+    #[test]
+    fn sortlist_cap() {
+        let entries = (1..=12).map(|idx| format!("10.{idx}.0.0/255.255.0.0"));
+        let content = format!("sortlist {}\n", entries.collect::<Vec<_>>().join(" "));
+        let resolver = parse(StubResolverBuilder::default(), &content);
+        assert_eq!(resolver.sortlist.len(), MAX_SORTLIST);
+        assert_eq!(resolver.sortlist[9].0, Ipv4Addr::new(10, 10, 0, 0));
+    }
+
+    #[test]
+    fn nameserver_cap() {
+        let content = "nameserver 192.0.2.1\nnameserver 192.0.2.2\nnameserver 192.0.2.3\nnameserver 192.0.2.4\n";
+        let resolver = parse(StubResolverBuilder::default(), content);
+        let expected: Vec<IpAddr> = ["192.0.2.1", "192.0.2.2", "192.0.2.3"]
+            .iter()
+            .map(|ip| IpAddr::from_str(ip).unwrap())
+            .collect();
+        assert_eq!(nameservers(&resolver), expected);
+
+        let resolver = parse(StubResolverBuilder::default().max_nameservers(4), content);
+        assert_eq!(nameservers(&resolver).len(), 4);
+        let resolver = parse(StubResolverBuilder::default().max_nameservers(1), content);
+        assert_eq!(nameservers(&resolver), expected[..1]);
+    }
+
+    #[test]
+    fn search_cap() {
+        let content = "search a b c d e f g h\n";
+        let resolver = parse(StubResolverBuilder::default(), content);
+        assert_eq!(resolver.search, ["a", "b", "c", "d", "e", "f"]);
+
+        let resolver = parse(
+            StubResolverBuilder::default().max_search_domains(2),
+            content,
+        );
+        assert_eq!(resolver.search, ["a", "b"]);
+    }
+
+    #[test]
+    fn domain_and_search() {
+        let resolver = parse(
+            StubResolverBuilder::default(),
+            "search a.example b.example\ndomain c.example\n",
+        );
+        assert_eq!(resolver.search, ["c.example"]);
+
+        let resolver = parse(
+            StubResolverBuilder::default(),
+            "domain c.example\nsearch a.example b.example # comment\n",
+        );
+        assert_eq!(resolver.search, ["a.example", "b.example"]);
+    }
+
+    #[test]
+    fn options() {
+        let resolver = parse(
+            StubResolverBuilder::default(),
+            "options ndots:3 timeout:1000 attempts:x rotate edns0\n",
+        );
+        assert_eq!(resolver.ndots, 3);
+        assert_eq!(resolver.timeout, Duration::from_secs(u8::MAX.into()));
+        assert_eq!(resolver.attempts, 2);
+        assert!(resolver.rotate);
+        assert_eq!(resolver.udp_payload_size, 1232);
+    }
 }
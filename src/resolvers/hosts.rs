@@ -8,18 +8,155 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 //
 
-use std::{borrow::Borrow, net::IpAddr, time::Duration};
+use std::{collections::HashMap, net::IpAddr, time::Duration};
 
-use crate::StubResolver;
+use crate::{
+    result::{LookupResult, Source},
+    AddressFamily, Config, HostEntry, StubResolver,
+};
+
+/// The entries of `/etc/hosts`, in file order, indexed by name and by address.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hosts {
+    entries: Vec<HostEntry>,
+    /// The entries of a name, which are lowercased and without a trailing dot.
+    by_name: HashMap<String, Vec<usize>>,
+    by_ip: HashMap<IpAddr, Vec<usize>>,
+}
+
+impl Hosts {
+    pub(crate) fn push(&mut self, entry: HostEntry) {
+        let idx = self.entries.len();
+        for host in entry.names() {
+            let indices = self.by_name.entry(normalize(host)).or_default();
+            if indices.last() != Some(&idx) {
+                indices.push(idx);
+            }
+        }
+        self.by_ip.entry(entry.ip).or_default().push(idx);
+        self.entries.push(entry);
+    }
+
+    /// The entries of the name, in file order.
+    pub(crate) fn by_name<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a HostEntry> + 'a {
+        let indices = self.by_name.get(&normalize(name));
+        indices.into_iter().flatten().map(|&idx| &self.entries[idx])
+    }
+
+    /// The entries of the address, in file order.
+    pub(crate) fn by_ip(&self, ip: IpAddr) -> impl Iterator<Item = &HostEntry> {
+        let indices = self.by_ip.get(&ip);
+        indices.into_iter().flatten().map(|&idx| &self.entries[idx])
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl Config {
+    /// The addresses of the host in `/etc/hosts`, in file order.
+    ///
+    /// Like glibc, the canonical name is the one of the first matching entry.
+    pub(super) fn query_hosts(&self, host: &str, family: AddressFamily) -> LookupResult {
+        let mut result = LookupResult::new(host, Source::Hosts);
+        let entries = self.hosts.by_name(host).filter(|entry| match entry.ip {
+            IpAddr::V4(_) => family.includes_ipv4(),
+            IpAddr::V6(_) => family.includes_ipv6(),
+        });
+        for entry in entries {
+            result
+                .canonical_name
+                .get_or_insert_with(|| entry.canonical.clone());
+            if !result.addrs.iter().any(|(ip, _)| *ip == entry.ip) {
+                result.addrs.push((entry.ip, Duration::ZERO));
+            }
+        }
+        result
+    }
+}
 
 impl StubResolver {
-    pub(super) fn query_hosts<'a>(
-        &'a self,
-        host: impl AsRef<str> + Borrow<str> + 'a,
-    ) -> impl Iterator<Item = (IpAddr, Duration)> + 'a {
-        self.entries
-            .iter()
-            .filter(move |entry| entry.hosts.contains(host.as_ref()))
-            .map(|entry| (entry.ip, Duration::ZERO))
+    /// The entries of `/etc/hosts` for the address, in file order.
+    pub fn reverse_hosts(&self, ip: IpAddr) -> Vec<HostEntry> {
+        self.config().hosts.by_ip(ip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(ip: &str, canonical: &str, aliases: &[&str]) -> HostEntry {
+        let aliases = aliases.iter().map(|alias| alias.to_string());
+        HostEntry::new(ip.parse().unwrap(), canonical, aliases)
+    }
+
+    fn hosts(entries: &[HostEntry]) -> Hosts {
+        let mut hosts = Hosts::default();
+        for entry in entries {
+            hosts.push(entry.clone());
+        }
+        hosts
+    }
+
+    fn ips<'a>(entries: impl Iterator<Item = &'a HostEntry>) -> Vec<String> {
+        entries.map(|entry| entry.ip.to_string()).collect()
+    }
+
+    #[test]
+    fn case_and_trailing_dot() {
+        let hosts = hosts(&[entry("192.0.2.1", "Host.Example", &["alias."])]);
+        assert_eq!(ips(hosts.by_name("host.example")), ["192.0.2.1"]);
+        assert_eq!(ips(hosts.by_name("HOST.EXAMPLE.")), ["192.0.2.1"]);
+        assert_eq!(ips(hosts.by_name("Alias")), ["192.0.2.1"]);
+        assert_eq!(ips(hosts.by_name("alias.")), ["192.0.2.1"]);
+        assert!(hosts.by_name("host").next().is_none());
+        assert!(hosts.by_name("example").next().is_none());
+    }
+
+    #[test]
+    fn file_order() {
+        let hosts = hosts(&[
+            entry("192.0.2.2", "other", &["host"]),
+            entry("2001:db8::1", "host", &[]),
+            entry("192.0.2.1", "host", &["other"]),
+        ]);
+        assert_eq!(
+            ips(hosts.by_name("host")),
+            ["192.0.2.2", "2001:db8::1", "192.0.2.1"]
+        );
+        assert_eq!(ips(hosts.by_name("other")), ["192.0.2.2", "192.0.2.1"]);
+    }
+
+    #[test]
+    fn once_per_entry() {
+        // The names that only differ by their case or trailing dot index the entry once.
+        let hosts = hosts(&[entry("192.0.2.1", "host", &["host.", "HOST"])]);
+        assert_eq!(ips(hosts.by_name("host")), ["192.0.2.1"]);
+    }
+
+    #[test]
+    fn reverse() {
+        let hosts = hosts(&[
+            entry("192.0.2.1", "first", &["alias"]),
+            entry("192.0.2.2", "other", &[]),
+            entry("192.0.2.1", "second", &[]),
+        ]);
+        let ip = "192.0.2.1".parse().unwrap();
+        let names: Vec<&str> = hosts
+            .by_ip(ip)
+            .map(|entry| entry.canonical.as_str())
+            .collect();
+        assert_eq!(names, ["first", "second"]);
+        assert!(hosts.by_ip("192.0.2.3".parse().unwrap()).next().is_none());
+    }
+
+    #[test]
+    fn aliases() {
+        let entry = entry("192.0.2.1", "host", &["a", "HOST", "b", "A", "a.", "b"]);
+        assert_eq!(entry.canonical, "host");
+        assert_eq!(entry.aliases, ["a", "b", "a."]);
+        assert_eq!(entry.names().collect::<Vec<_>>(), ["host", "a", "b", "a."]);
     }
 }
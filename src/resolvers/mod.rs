@@ -8,12 +8,34 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 //
 
-use std::{borrow::Borrow, net::IpAddr, str::FromStr, time::Duration};
+use std::{
+    borrow::Borrow,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
 
-use crate::{errors::Error, StubResolver};
+use domain::base::{name::UncertainName, Message, Rtype};
+
+use crate::{
+    coalesce::Key,
+    errors::Error,
+    lookups::udp::Sockets,
+    nsswitch::{Action, Service, Status},
+    options::{LookupContext, LookupOptions},
+    result::{LookupResult, Source},
+    sort::apply_sortlist,
+    AddressFamily, Config, NameserverStats, NsSwitch, StubResolver,
+};
 
 mod hosts;
 mod resolv;
+mod routes;
+mod special;
+
+pub(crate) use hosts::Hosts;
+pub(crate) use routes::Routes;
+pub use special::SpecialUse;
 
 impl StubResolver {
     pub async fn lookup<'a, B>(
@@ -23,22 +45,139 @@ impl StubResolver {
     where
         B: FromIterator<(IpAddr, Duration)>,
     {
+        self.lookup_with(host, LookupOptions::default()).await
+    }
+
+    pub async fn lookup_with<'a, B>(
+        &'a self,
+        host: impl AsRef<str> + Borrow<str> + 'a,
+        options: LookupOptions,
+    ) -> Result<B, Error>
+    where
+        B: FromIterator<(IpAddr, Duration)>,
+    {
+        let result = self.resolve(host, options).await?;
+        Ok(result.into_iter().collect())
+    }
+
+    /// Looks up the addresses of the host, along with the details of how they were found.
+    pub async fn resolve<'a>(
+        &'a self,
+        host: impl AsRef<str> + Borrow<str> + 'a,
+        options: LookupOptions,
+    ) -> Result<LookupResult, Error> {
+        self.resolve_with_sockets(host, options, None).await
+    }
+
+    /// Like [`StubResolver::resolve`], with the UDP sockets shared by a batch of lookups.
+    pub(crate) async fn resolve_with_sockets<'a>(
+        &'a self,
+        host: impl AsRef<str> + Borrow<str> + 'a,
+        options: LookupOptions,
+        sockets: Option<Sockets>,
+    ) -> Result<LookupResult, Error> {
+        let family = options.family;
+        let in_family = |ip: &IpAddr| match ip {
+            IpAddr::V4(_) => family.includes_ipv4(),
+            IpAddr::V6(_) => family.includes_ipv6(),
+        };
+
         if let Ok(ip) = IpAddr::from_str(host.as_ref()) {
-            return Ok(std::iter::once((ip, Duration::ZERO)).collect());
+            let mut result = LookupResult::new(host.as_ref(), Source::Literal);
+            if in_family(&ip) {
+                result.addrs.push((ip, Duration::ZERO));
+            }
+            return Ok(result);
         }
 
-        let mut count: u16 = 0;
-        let addrs = self
-            .query_hosts(host.as_ref())
-            .inspect(|_| count += 1)
-            .collect();
+        // The services are tried in the order of `/etc/nsswitch.conf`.
+        let use_hosts = options.hosts;
+        let mut ctx = LookupContext::new(options, self.config());
+        ctx.sockets = sockets;
+        let config = &ctx.config;
+        let deadline = ctx.options.deadline.or(self.deadline);
+        let mut result = LookupResult::new(host.as_ref(), Source::Dns);
+        for entry in config.nsswitch.entries() {
+            result = match entry.service {
+                Service::Files if use_hosts => config.query_hosts(host.as_ref(), family),
+                Service::Dns => match self.query_special_use(host.as_ref(), family) {
+                    // Special-use names never reach the nameservers, even with the search domains.
+                    Some(result) => result,
+                    None => {
+                        ctx.run(deadline, self.query_resolv(host.as_ref(), &ctx))
+                            .await?
+                    }
+                },
+                Service::Myhostname => config.query_myhostname(host.as_ref(), family),
+                Service::Files | Service::Other(_) => continue,
+            };
+            let status = match (&result.source, result.nameserver) {
+                _ if !result.is_empty() => Status::Success,
+                (Source::Dns, None) => Status::Unavail,
+                _ => Status::NotFound,
+            };
+            if entry.action(status) == Action::Return {
+                break;
+            }
+        }
+        Ok(config.sorted(result))
+    }
 
-        if count != 0 {
-            return Ok(addrs);
+    /// Queries the nameservers for the records of the given type.
+    ///
+    /// The name is not expanded with the search domains, and `/etc/hosts` is not consulted.
+    /// The special-use names fail with [`Error::SpecialUseName`] rather than being sent to the nameservers.
+    /// The nameservers that fail or refuse the query are skipped, see [`Error::ResponseCode`].
+    pub async fn query(
+        &self,
+        name: impl AsRef<str>,
+        rtype: Rtype,
+        options: LookupOptions,
+    ) -> Result<Message<Vec<u8>>, Error> {
+        let qname = UncertainName::<Vec<u8>>::from_str(name.as_ref())?.into_absolute()?;
+        if self
+            .query_special_use(name.as_ref(), AddressFamily::Any)
+            .is_some()
+        {
+            let name = name.as_ref().to_owned();
+            return Err(Error::SpecialUseName { name });
         }
+        let ctx = LookupContext::new(options, self.config());
+        ctx.querying_name(name.as_ref().trim_end_matches('.'));
+        let deadline = ctx.options.deadline.or(self.deadline);
+        let key = Key::new(name.as_ref(), Some(rtype), &ctx.options);
+        let nameservers = ctx.config.nameservers_for(name.as_ref());
+        let query = self
+            .queries
+            .run(key, || self.dns_query(qname, rtype, nameservers, &ctx));
+        let message = ctx.run(deadline, query).await?;
+        message.ok_or(Error::NoResponse {})
+    }
 
-        let addrs = self.query_resolv::<B>(host.as_ref()).await?;
+    /// The order of the services used to look up the hosts, see [`NsSwitch::unsupported`].
+    pub fn nsswitch(&self) -> NsSwitch {
+        self.config().nsswitch.clone()
+    }
 
-        Ok(addrs)
+    /// The observed health and latency of the configured nameservers.
+    pub fn nameserver_stats(&self) -> Vec<(SocketAddr, NameserverStats)> {
+        self.config()
+            .nameservers
+            .iter()
+            .map(|ns| (ns.socket_addr(), self.stats.get(ns.socket_addr())))
+            .collect()
+    }
+}
+
+impl Config {
+    fn sorted(&self, mut result: LookupResult) -> LookupResult {
+        if let Some(table) = &self.policy_table {
+            table.sort(&mut result.addrs);
+        }
+        // Like glibc, the `sortlist` only applies to the answers of the nameservers.
+        if result.source == Source::Dns {
+            apply_sortlist(&self.sortlist, &mut result.addrs);
+        }
+        result
     }
 }
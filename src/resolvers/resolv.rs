@@ -9,165 +9,403 @@
 //
 
 use std::{
-    net::{IpAddr, SocketAddr},
+    future::Future,
+    net::SocketAddr,
     rc::Rc,
     str::FromStr,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use domain::base::{
-    iana::Class, name::UncertainName, wire::Composer, MessageBuilder, Question, Rtype,
-    StaticCompressor, ToName,
+    iana::{Class, Rcode},
+    name::UncertainName,
+    wire::Composer,
+    Message, MessageBuilder, Question, Rtype, StaticCompressor, ToName,
 };
 
-use crate::{addr::IpAddresses, errors::Error, StubResolver};
+use crate::{
+    addr::IpAddresses,
+    coalesce::Key,
+    errors::Error,
+    hedge::race,
+    lookups::{
+        udp::{self, Sockets},
+        Query, Tcp, Transport,
+    },
+    options::{LookupContext, Search},
+    result::{LookupResult, Source},
+    stats::Stats,
+    Nameserver, Protocol, StubResolver,
+};
+
+/// The settings shared by the queries of a lookup.
+struct Settings {
+    attempts: u8,
+    nameservers: usize,
+    udp_payload_size: u16,
+    recursion_desired: bool,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    protocol: Option<Protocol>,
+    sockets: Option<Sockets>,
+}
 
 impl StubResolver {
-    pub(super) async fn query_resolv<B>(&self, name: &str) -> Result<B, Error>
-    where
-        B: FromIterator<(IpAddr, Duration)>,
-    {
-        self.dns_with_search(name).await
+    pub(super) async fn query_resolv(
+        &self,
+        name: &str,
+        ctx: &LookupContext,
+    ) -> Result<LookupResult, Error> {
+        self.dns_with_search(name, ctx).await
     }
 
-    async fn dns_with_search<B>(&self, name: &str) -> Result<B, Error>
-    where
-        B: FromIterator<(IpAddr, Duration)>,
-    {
-        // See if we should just use global scope.
+    async fn dns_with_search(
+        &self,
+        name: &str,
+        ctx: &LookupContext,
+    ) -> Result<LookupResult, Error> {
+        // See if we should try the name as is first.
         let num_dots = memchr::Memchr::new(b'.', name.as_bytes()).count();
-        let global_scope = num_dots >= self.ndots as usize || name.ends_with(".");
+        let absolute = name.ends_with('.');
         let name = name.trim_end_matches('.');
 
-        if global_scope {
-            let it = self.search.iter();
-            // Try the name with the search domains.
-            let mut host = String::from(name);
-            host.push('.');
-            let size = host.len();
-            for search in it {
-                // Try the name with the search domains.
-                host.truncate(size);
-                host.push_str(search.trim_start_matches('.'));
-
-                let name = UncertainName::<Vec<u8>>::from_str(&host)?.into_absolute()?;
-                if let Ok(addrs) = self.dns_lookup(name).await {
-                    return Ok(addrs);
-                }
+        let config = &ctx.config;
+        let searched = config
+            .search
+            .iter()
+            .map(|search| format!("{}.{}", name, search.trim_matches('.')));
+        let mut names: Vec<String> = Vec::new();
+        // Like glibc, the aliases only apply to names without dots, and are not searched.
+        let alias = match num_dots {
+            0 => config.host_aliases.get(&name.to_ascii_lowercase()),
+            _ => None,
+        };
+        match ctx.options.search {
+            _ if alias.is_some() => names.extend(alias.cloned()),
+            Search::Disabled => names.push(name.to_owned()),
+            _ if absolute => names.push(name.to_owned()),
+            Search::Only => names.extend(searched),
+            Search::Auto if num_dots >= config.ndots as usize => {
+                names.push(name.to_owned());
+                names.extend(searched);
+            }
+            Search::Auto => {
+                names.extend(searched);
+                names.push(name.to_owned());
             }
-            FromIterator::from_iter(std::iter::empty())
-        } else {
-            let name = UncertainName::<Vec<u8>>::from_str(name)?.into_absolute()?;
-            // Preform a DNS search on just the name.
-            self.dns_lookup(name).await
         }
+
+        let mut result = LookupResult::new(name, Source::Dns);
+        for host in names {
+            ctx.querying_name(&host);
+            let name = UncertainName::<Vec<u8>>::from_str(&host)?.into_absolute()?;
+            let key = Key::new(&host, None, &ctx.options);
+            let nameservers = config.nameservers_for(&host);
+            result = self
+                .lookups
+                .run(key, || self.dns_lookup(name, nameservers, ctx))
+                .await?;
+            result.name = host;
+            if !result.is_empty() {
+                break;
+            }
+        }
+        Ok(result)
     }
 
     /// Preform a manual lookup for the name.
-    async fn dns_lookup<B>(&self, name: impl ToName) -> Result<B, Error>
+    async fn dns_lookup(
+        &self,
+        name: impl ToName,
+        nameservers: &[Nameserver],
+        ctx: &LookupContext,
+    ) -> Result<LookupResult, Error> {
+        let result = self
+            .race_nameservers(nameservers, ctx, |nameserver| {
+                self.query_name_and_nameserver(&name, nameserver, nameservers, ctx)
+            })
+            .await;
+        match result {
+            Some(Ok(result)) => Ok(result),
+            _ => Ok(LookupResult::new(String::new(), Source::Dns)),
+        }
+    }
+
+    /// Preform a manual query of any type for the name.
+    ///
+    /// Returns `None` if none of the nameservers has answered.
+    pub(crate) async fn dns_query(
+        &self,
+        name: impl ToName,
+        rtype: Rtype,
+        nameservers: &[Nameserver],
+        ctx: &LookupContext,
+    ) -> Result<Option<Message<Vec<u8>>>, Error> {
+        let result = self
+            .race_nameservers(nameservers, ctx, |nameserver| async {
+                let timeout = self.nameserver_timeout(nameserver, ctx);
+                let settings = self.settings(nameservers, ctx);
+                let question = Question::new(&name, rtype, Class::IN);
+                query_question_and_nameserver(question, nameserver, timeout, &settings)
+                    .await?
+                    .ok_or(Error::NoResponse {})
+            })
+            .await;
+        match result {
+            Some(Ok(message)) => Ok(Some(message)),
+            Some(Err(err @ Error::ResponseCode { .. })) => Err(err),
+            _ => Ok(None),
+        }
+    }
+
+    /// Runs the exchange on the nameservers according to the strategy, and returns the first successful result.
+    async fn race_nameservers<'a, T, F, Fut>(
+        &'a self,
+        nameservers: &'a [Nameserver],
+        ctx: &'a LookupContext,
+        exchange: F,
+    ) -> Option<Result<T, Error>>
     where
-        B: FromIterator<(IpAddr, Duration)>,
+        F: Fn(&'a Nameserver) -> Fut,
+        Fut: Future<Output = Result<T, Error>> + 'a,
     {
-        let it = self.nameservers.iter();
-        for nameserver in it {
-            if let Ok(addrs) = self.query_name_and_nameserver(&name, nameserver).await {
-                return Ok(addrs);
+        let nameservers = self
+            .stats
+            .select(nameservers, ctx.config.rotate)
+            .into_iter()
+            .filter(|ns| match ctx.options.protocol {
+                Some(protocol @ (Protocol::Tls | Protocol::Https)) => ns.protocol == protocol,
+                _ => true,
+            });
+        let queries =
+            nameservers.map(|nameserver| self.record(nameserver, ctx, exchange(nameserver)));
+        race(queries, self.strategy).await
+    }
+
+    /// Runs the exchange with the nameserver, and updates its statistics.
+    async fn record<T>(
+        &self,
+        nameserver: &Nameserver,
+        ctx: &LookupContext,
+        exchange: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let addr = nameserver.socket_addr();
+        ctx.querying_nameserver(addr);
+        let start = Instant::now();
+        let pending = Pending {
+            stats: &self.stats,
+            addr,
+            start,
+        };
+        let result = exchange.await;
+        std::mem::forget(pending);
+        match result {
+            Ok(_) => self.stats.success(addr, start.elapsed()),
+            Err(_) => {
+                let penalty = self.nameserver_timeout(nameserver, ctx);
+                self.stats.failure(addr, penalty.max(start.elapsed()));
             }
         }
-        Ok(FromIterator::from_iter(std::iter::empty()))
+        result
+    }
+
+    fn nameserver_timeout(&self, nameserver: &Nameserver, ctx: &LookupContext) -> Duration {
+        ctx.options
+            .timeout
+            .or(nameserver.timeout)
+            .unwrap_or(ctx.config.timeout)
+    }
+
+    fn settings(&self, nameservers: &[Nameserver], ctx: &LookupContext) -> Settings {
+        let options = &ctx.options;
+        Settings {
+            attempts: options.attempts.unwrap_or(ctx.config.attempts),
+            nameservers: nameservers.iter().filter(|ns| ns.enabled).count(),
+            udp_payload_size: ctx.config.udp_payload_size,
+            recursion_desired: options.recursion_desired,
+            dnssec_ok: options.dnssec_ok,
+            checking_disabled: options.checking_disabled,
+            protocol: options.protocol,
+            sockets: ctx.sockets.clone(),
+        }
     }
 
     /// Poll for the name on the given nameserver.
-    async fn query_name_and_nameserver<B>(
+    async fn query_name_and_nameserver(
         &self,
         name: impl ToName,
-        nameserver: &SocketAddr,
-    ) -> Result<B, Error>
-    where
-        B: FromIterator<(IpAddr, Duration)>,
-    {
+        nameserver: &Nameserver,
+        nameservers: &[Nameserver],
+        ctx: &LookupContext,
+    ) -> Result<LookupResult, Error> {
+        let options = &ctx.options;
+        let timeout = self.nameserver_timeout(nameserver, ctx);
+        let settings = self.settings(nameservers, ctx);
+
         // Try to poll for an IPv4 address first.
-        let ipv4 = query_question_and_nameserver(
-            Question::new(&name, Rtype::A, Class::IN),
-            nameserver,
-            self.attempts,
-            self.timeout,
-            self.udp_payload_size,
-        );
-
-        let ipv6 = query_question_and_nameserver(
-            Question::new(&name, Rtype::AAAA, Class::IN),
-            nameserver,
-            self.attempts,
-            self.timeout,
-            self.udp_payload_size,
-        );
+        let ipv4 = async {
+            if !options.family.includes_ipv4() {
+                return Ok(None);
+            }
+            let question = Question::new(&name, Rtype::A, Class::IN);
+            query_question_and_nameserver(question, nameserver, timeout, &settings).await
+        };
+
+        let ipv6 = async {
+            if !options.family.includes_ipv6() {
+                return Ok(None);
+            }
+            let question = Question::new(&name, Rtype::AAAA, Class::IN);
+            query_question_and_nameserver(question, nameserver, timeout, &settings).await
+        };
 
         let (ipv4, ipv6) = monoio::join!(ipv4, ipv6);
-        let ipv4 = ipv4?;
-        let ipv6 = ipv6?;
-        let addrs = ipv4.iter().chain(ipv6.iter()).flat_map(|x| x.iter());
-        let addrs = FromIterator::from_iter(addrs);
-        Ok(addrs)
+        let ipv4 = ipv4?.map(IpAddresses::from);
+        let ipv6 = ipv6?.map(IpAddresses::from);
+        if ipv4.is_none() && ipv6.is_none() {
+            return Err(Error::NoResponse {});
+        }
+        let received = Instant::now();
+        let responses = ipv4.iter().chain(ipv6.iter());
+
+        let mut result = LookupResult::new(String::new(), Source::Dns);
+        result.nameserver = Some(nameserver.socket_addr());
+        result.addrs = responses.clone().flat_map(|x| x.iter()).collect();
+        result.canonical_name = responses.clone().find_map(|x| x.canonical_name());
+        result.authenticated = responses.clone().all(|x| x.authenticated());
+        result.expires = result.addrs.iter().map(|(_, ttl)| received + *ttl).min();
+        Ok(result)
+    }
+}
+
+/// Records the exchange with a nameserver as abandoned if it is dropped before completing,
+/// e.g. when another nameserver answered first.
+struct Pending<'a> {
+    stats: &'a Stats,
+    addr: SocketAddr,
+    start: Instant,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        self.stats.abandoned(self.addr, self.start.elapsed());
     }
 }
 
 fn create_message<T: Composer + Default>(
     id: u16,
     question: Question<impl ToName>,
-    udp_payload_size: u16,
+    settings: &Settings,
 ) -> Result<StaticCompressor<T>, Error> {
     // Create the DNS query.
     let mut message = MessageBuilder::from_target(StaticCompressor::new(Default::default()))
         .map_err(|_| Error::AppendError {})?;
-    message.header_mut().set_rd(true);
+    message.header_mut().set_rd(settings.recursion_desired);
+    message.header_mut().set_cd(settings.checking_disabled);
     message.header_mut().set_id(id);
     let mut message = message.question();
     message.push(question)?;
     let mut message = message.additional();
     message.opt(|opt| {
-        opt.set_udp_payload_size(udp_payload_size);
+        opt.set_udp_payload_size(settings.udp_payload_size);
+        opt.set_dnssec_ok(settings.dnssec_ok);
         Ok(())
     })?;
     Ok(message.finish())
 }
 
 /// Poll for a DNS response on the given nameserver.
+///
+/// Uses the custom transport of the nameserver if there is one, otherwise the built-in transport of its protocol.
 async fn query_question_and_nameserver(
     question: Question<impl ToName>,
-    nameserver: &SocketAddr,
-    attempts: u8,
+    nameserver: &Nameserver,
     timeout_duration: Duration,
-    udp_payload_size: u16,
-) -> Result<Option<IpAddresses>, Error> {
+    settings: &Settings,
+) -> Result<Option<Message<Vec<u8>>>, Error> {
     let id = fastrand::u16(..);
-    let message = create_message::<Vec<u8>>(id, question, udp_payload_size)?;
-    let data: Rc<Vec<u8>> = Rc::from(message.into_target());
-
-    // The query may be too large, so we need to use TCP.
-    if data.len() <= udp_payload_size as usize {
-        if let Ok(Some(addrs)) = crate::lookups::udp::query(
-            id,
-            data.clone(),
-            nameserver,
-            attempts,
-            timeout_duration,
-            udp_payload_size,
-        )
-        .await
-        {
-            return Ok(Some(addrs));
+    let message = create_message::<Vec<u8>>(id, question, settings)?;
+    let query = Query {
+        id,
+        message: Rc::from(message.into_target()),
+        nameserver: nameserver.socket_addr(),
+        tls_name: nameserver.tls_name.clone(),
+        attempts: settings.attempts,
+        timeout: timeout_duration,
+        nameservers: settings.nameservers,
+        udp_payload_size: settings.udp_payload_size,
+    };
+
+    // Plain nameservers may be switched between UDP and TCP for a lookup.
+    let protocol = match (settings.protocol, nameserver.protocol) {
+        (Some(preferred @ (Protocol::Udp | Protocol::Tcp)), Protocol::Udp | Protocol::Tcp) => {
+            preferred
         }
+        (_, protocol) => protocol,
+    };
+
+    let response = match (&nameserver.transport, protocol) {
+        (Some(transport), _) => transport.query(&query).await?,
+        (None, Protocol::Udp) => {
+            let mut response = None;
+            // The query may be too large, so we need to use TCP.
+            if query.message.len() <= settings.udp_payload_size as usize {
+                if let Ok(Some(buf)) = udp::query(&query, settings.sockets.as_ref()).await {
+                    response = Some(buf);
+                }
+            }
+            match response {
+                Some(buf) => Some(buf),
+                // We were unable to complete the query over UDP, use TCP instead.
+                None => Tcp.query(&query).await?,
+            }
+        }
+        (None, Protocol::Tcp) => Tcp.query(&query).await?,
+        (None, protocol) => return Err(Error::UnsupportedProtocol { protocol }),
+    };
+
+    let Some(buf) = response else {
+        return Ok(None);
+    };
+    let message = Message::from_octets(buf)?;
+    // Custom transports are not trusted to check the ID.
+    if message.header().id() != id {
+        return Ok(None);
     }
+    accept(message).map(Some)
+}
 
-    // We were unable to complete the query over UDP, use TCP instead.
-    crate::lookups::tcp::query(
-        id,
-        data,
-        nameserver,
-        attempts,
-        timeout_duration,
-        udp_payload_size,
-    )
-    .await
+/// Only the answers and the names that do not exist end the race,
+/// the nameservers that fail or refuse the query are skipped like in glibc.
+fn accept(message: Message<Vec<u8>>) -> Result<Message<Vec<u8>>, Error> {
+    let rcode = message.header().rcode();
+    if rcode != Rcode::NOERROR && rcode != Rcode::NXDOMAIN {
+        return Err(Error::ResponseCode { rcode });
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(rcode: u8) -> Message<Vec<u8>> {
+        Message::from_octets(vec![0, 1, 0x81, 0x80 | rcode, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap()
+    }
+
+    #[test]
+    fn rcodes() {
+        assert!(accept(response(0)).is_ok());
+        assert!(accept(response(3)).is_ok());
+        for (code, rcode) in [
+            (1, Rcode::FORMERR),
+            (2, Rcode::SERVFAIL),
+            (5, Rcode::REFUSED),
+        ] {
+            assert!(matches!(
+                accept(response(code)),
+                Err(Error::ResponseCode { rcode: found }) if found == rcode
+            ));
+        }
+    }
 }
@@ -0,0 +1,124 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use super::special::is_within;
+use crate::{Config, Nameserver};
+
+/// The nameservers of a domain and its subdomains.
+#[derive(Debug, Clone)]
+struct Route {
+    /// Without the trailing dot, empty for the root.
+    domain: String,
+    nameservers: Vec<Nameserver>,
+}
+
+/// Routes the names to nameservers by their longest matching domain (split DNS).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Routes(Vec<Route>);
+
+impl Routes {
+    /// Adds a route, which replaces an earlier one of the same domain.
+    pub(crate) fn add(&mut self, domain: &str, nameservers: Vec<Nameserver>) {
+        let domain = domain.trim_matches('.').to_ascii_lowercase();
+        self.0.retain(|route| route.domain != domain);
+        self.0.push(Route {
+            domain,
+            nameservers,
+        });
+    }
+
+    /// The nameservers of the longest domain of the name, if any.
+    fn find(&self, name: &str) -> Option<&[Nameserver]> {
+        let name = name.trim_end_matches('.');
+        self.0
+            .iter()
+            .filter(|route| route.domain.is_empty() || is_within(name, &route.domain))
+            .max_by_key(|route| route.domain.len())
+            .map(|route| route.nameservers.as_slice())
+    }
+}
+
+impl Config {
+    /// The nameservers to query for the name, according to the routes.
+    pub(crate) fn nameservers_for(&self, name: &str) -> &[Nameserver] {
+        self.routes.find(name).unwrap_or(&self.nameservers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    /// The routes of the domains, the nameserver of the nth domain being `192.0.2.n`.
+    fn routes_of(domains: &[&str]) -> Routes {
+        let mut routes = Routes::default();
+        for (idx, domain) in (1..).zip(domains) {
+            let addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, idx));
+            routes.add(domain, vec![Nameserver::new(addr)]);
+        }
+        routes
+    }
+
+    /// The index of the domain of the route of the name.
+    fn find(routes: &Routes, name: &str) -> Option<u8> {
+        let nameservers = routes.find(name)?;
+        match nameservers[0].addr {
+            IpAddr::V4(addr) => Some(addr.octets()[3]),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    #[test]
+    fn catch_all() {
+        let routes = routes_of(&[".", "corp.example"]);
+        assert_eq!(find(&routes, "www.example.com"), Some(1));
+        assert_eq!(find(&routes, "www.corp.example"), Some(2));
+        assert_eq!(find(&routes, "localhost"), Some(1));
+
+        let routes = routes_of(&["corp.example"]);
+        assert_eq!(find(&routes, "www.example.com"), None);
+    }
+
+    #[test]
+    fn longest_domain() {
+        let routes = routes_of(&["eng.corp.example", "example", "corp.example"]);
+        assert_eq!(find(&routes, "host.eng.corp.example"), Some(1));
+        assert_eq!(find(&routes, "host.corp.example"), Some(3));
+        assert_eq!(find(&routes, "corp.example"), Some(3));
+        assert_eq!(find(&routes, "host.example"), Some(2));
+    }
+
+    #[test]
+    fn case_and_trailing_dot() {
+        let routes = routes_of(&["Corp.Example."]);
+        assert_eq!(find(&routes, "HOST.corp.EXAMPLE."), Some(1));
+        assert_eq!(find(&routes, "corp.example."), Some(1));
+        assert_eq!(find(&routes, "host.corp.example"), Some(1));
+    }
+
+    #[test]
+    fn near_miss() {
+        let routes = routes_of(&["corp.example"]);
+        assert_eq!(find(&routes, "xcorp.example"), None);
+        assert_eq!(find(&routes, "host.xcorp.example"), None);
+        assert_eq!(find(&routes, "corp.example.com"), None);
+
+        let routes = routes_of(&["corp.example", "."]);
+        assert_eq!(find(&routes, "xcorp.example"), Some(2));
+    }
+
+    #[test]
+    fn replaced() {
+        let routes = routes_of(&["corp.example", "CORP.example."]);
+        assert_eq!(find(&routes, "host.corp.example"), Some(2));
+    }
+}
@@ -0,0 +1,233 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use crate::{
+    result::{LookupResult, Source},
+    AddressFamily, Config, StubResolver,
+};
+
+/// Which special-use names are answered locally, rather than by the nameservers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpecialUse {
+    /// `localhost.` and its subdomains resolve to the loopback addresses (RFC 6761).
+    pub localhost: bool,
+    /// `invalid.` and its subdomains do not exist (RFC 6761).
+    pub invalid: bool,
+    /// `onion.` and its subdomains are not looked up (RFC 7686).
+    pub onion: bool,
+}
+
+impl Default for SpecialUse {
+    fn default() -> Self {
+        Self {
+            localhost: true,
+            invalid: true,
+            onion: true,
+        }
+    }
+}
+
+impl SpecialUse {
+    /// Sends all the names to the nameservers.
+    pub fn none() -> Self {
+        Self {
+            localhost: false,
+            invalid: false,
+            onion: false,
+        }
+    }
+
+    pub fn localhost(mut self, enabled: bool) -> Self {
+        self.localhost = enabled;
+        self
+    }
+
+    pub fn invalid(mut self, enabled: bool) -> Self {
+        self.invalid = enabled;
+        self
+    }
+
+    pub fn onion(mut self, enabled: bool) -> Self {
+        self.onion = enabled;
+        self
+    }
+}
+
+/// Whether the name is the domain or one of its subdomains.
+pub(super) fn is_within(name: &str, domain: &str) -> bool {
+    let (name, domain) = (name.as_bytes(), domain.as_bytes());
+    match name.len().checked_sub(domain.len()) {
+        Some(0) => name.eq_ignore_ascii_case(domain),
+        Some(start) => name[start - 1] == b'.' && name[start..].eq_ignore_ascii_case(domain),
+        None => false,
+    }
+}
+
+impl SpecialUse {
+    /// Answers the special-use names, `None` for the other names.
+    ///
+    /// The names that do not exist have no addresses.
+    fn query(self, host: &str, family: AddressFamily) -> Option<LookupResult> {
+        let special_use = self;
+        let name = host.trim_end_matches('.');
+        let mut result = LookupResult::new(host, Source::SpecialUse);
+        if special_use.localhost && is_within(name, "localhost") {
+            if family.includes_ipv6() {
+                result
+                    .addrs
+                    .push((IpAddr::V6(Ipv6Addr::LOCALHOST), Duration::ZERO));
+            }
+            if family.includes_ipv4() {
+                result
+                    .addrs
+                    .push((IpAddr::V4(Ipv4Addr::LOCALHOST), Duration::ZERO));
+            }
+            return Some(result);
+        }
+        if special_use.invalid && is_within(name, "invalid")
+            || special_use.onion && is_within(name, "onion")
+        {
+            return Some(result);
+        }
+        None
+    }
+}
+
+impl StubResolver {
+    pub(super) fn query_special_use(
+        &self,
+        host: &str,
+        family: AddressFamily,
+    ) -> Option<LookupResult> {
+        self.special_use.query(host, family)
+    }
+}
+
+impl Config {
+    /// Answers the local hostname, like `nss-myhostname` when no address is configured.
+    pub(super) fn query_myhostname(&self, host: &str, family: AddressFamily) -> LookupResult {
+        let mut result = LookupResult::new(host, Source::Myhostname);
+        let name = host.trim_end_matches('.');
+        let is_hostname = self
+            .hostname
+            .as_deref()
+            .is_some_and(|hostname| hostname.eq_ignore_ascii_case(name));
+        if is_hostname || is_within(name, "localhost") {
+            if family.includes_ipv6() {
+                result
+                    .addrs
+                    .push((IpAddr::V6(Ipv6Addr::LOCALHOST), Duration::ZERO));
+            }
+            if family.includes_ipv4() {
+                let ip = match is_hostname {
+                    true => Ipv4Addr::new(127, 0, 0, 2),
+                    false => Ipv4Addr::LOCALHOST,
+                };
+                result.addrs.push((IpAddr::V4(ip), Duration::ZERO));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(special_use: SpecialUse, host: &str, family: AddressFamily) -> Option<Vec<IpAddr>> {
+        let result = special_use.query(host, family)?;
+        assert_eq!(result.source, Source::SpecialUse);
+        Some(result.addrs.into_iter().map(|(ip, _)| ip).collect())
+    }
+
+    const LOCALHOST: [IpAddr; 2] = [
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+    ];
+
+    #[test]
+    fn within() {
+        assert!(is_within("localhost", "localhost"));
+        assert!(is_within("foo.localhost", "localhost"));
+        assert!(is_within("Foo.LocalHost", "localhost"));
+        assert!(!is_within("notlocalhost", "localhost"));
+        assert!(!is_within("localhost.example", "localhost"));
+        assert!(!is_within("host", "localhost"));
+    }
+
+    #[test]
+    fn localhost() {
+        let any = AddressFamily::Any;
+        let special_use = SpecialUse::default();
+        assert_eq!(query(special_use, "localhost", any).unwrap(), LOCALHOST);
+        assert_eq!(query(special_use, "localhost.", any).unwrap(), LOCALHOST);
+        assert_eq!(query(special_use, "foo.localhost", any).unwrap(), LOCALHOST);
+        assert_eq!(query(special_use, "notlocalhost", any), None);
+        assert_eq!(query(special_use, "localhost.example", any), None);
+    }
+
+    #[test]
+    fn families() {
+        let special_use = SpecialUse::default();
+        let ipv4 = query(special_use, "localhost", AddressFamily::Ipv4).unwrap();
+        assert_eq!(ipv4, [IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let ipv6 = query(special_use, "localhost", AddressFamily::Ipv6).unwrap();
+        assert_eq!(ipv6, [IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+        let onion = query(special_use, "example.onion", AddressFamily::Ipv4).unwrap();
+        assert!(onion.is_empty());
+    }
+
+    #[test]
+    fn nonexistent() {
+        let any = AddressFamily::Any;
+        let special_use = SpecialUse::default();
+        assert_eq!(query(special_use, "invalid", any), Some(Vec::new()));
+        assert_eq!(query(special_use, "host.invalid.", any), Some(Vec::new()));
+        assert_eq!(query(special_use, "example.onion", any), Some(Vec::new()));
+        assert_eq!(
+            query(special_use, "www.example.ONION", any),
+            Some(Vec::new())
+        );
+        assert_eq!(query(special_use, "invalid.example", any), None);
+        assert_eq!(query(special_use, "notonion", any), None);
+    }
+
+    #[test]
+    fn toggles() {
+        let any = AddressFamily::Any;
+        let special_use = SpecialUse::none();
+        for host in [
+            "localhost",
+            "foo.localhost",
+            "host.invalid",
+            "example.onion",
+        ] {
+            assert_eq!(query(special_use, host, any), None);
+        }
+        let special_use = SpecialUse::default().localhost(false);
+        assert_eq!(query(special_use, "localhost", any), None);
+        assert_eq!(query(special_use, "host.invalid", any), Some(Vec::new()));
+        let special_use = SpecialUse::default().invalid(false);
+        assert_eq!(query(special_use, "host.invalid", any), None);
+        assert_eq!(query(special_use, "example.onion", any), Some(Vec::new()));
+        let special_use = SpecialUse::default().onion(false);
+        assert_eq!(query(special_use, "example.onion", any), None);
+        assert_eq!(query(special_use, "localhost", any).unwrap(), LOCALHOST);
+        let special_use = SpecialUse::none().onion(true);
+        assert_eq!(query(special_use, "example.onion", any), Some(Vec::new()));
+        assert_eq!(query(special_use, "localhost", any), None);
+    }
+}
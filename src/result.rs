@@ -0,0 +1,83 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// Where the addresses of a [`LookupResult`] come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Source {
+    /// The name was an IP address.
+    Literal,
+    /// `/etc/hosts`.
+    Hosts,
+    /// A nameserver.
+    Dns,
+    /// A special-use name, see [`crate::SpecialUse`].
+    SpecialUse,
+    /// The local hostname, see [`crate::Service::Myhostname`].
+    Myhostname,
+}
+
+/// The outcome of [`crate::StubResolver::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupResult {
+    /// Pairs of (ip, ttl).
+    pub addrs: Vec<(IpAddr, Duration)>,
+    /// The name that was looked up, after the search domains were applied.
+    pub name: String,
+    /// The name of the addresses, after following CNAMEs.
+    pub canonical_name: Option<String>,
+    pub source: Source,
+    /// The nameserver that answered, if any.
+    pub nameserver: Option<SocketAddr>,
+    /// When the addresses expire, according to the smallest TTL.
+    pub expires: Option<Instant>,
+    /// Whether the nameserver has validated the answer with DNSSEC (the AD bit).
+    pub authenticated: bool,
+}
+
+impl LookupResult {
+    pub(crate) fn new(name: impl Into<String>, source: Source) -> Self {
+        Self {
+            addrs: Vec::new(),
+            name: name.into(),
+            canonical_name: None,
+            source,
+            nameserver: None,
+            expires: None,
+            authenticated: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    pub fn ips(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.addrs.iter().map(|(ip, _)| *ip)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, Duration)> + '_ {
+        self.addrs.iter().copied()
+    }
+}
+
+impl IntoIterator for LookupResult {
+    type Item = (IpAddr, Duration);
+    type IntoIter = std::vec::IntoIter<(IpAddr, Duration)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.addrs.into_iter()
+    }
+}
@@ -0,0 +1,166 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    str::FromStr,
+    time::Duration,
+};
+
+use crate::{errors::Error, StubResolver};
+
+/// A parsed `host:port`, `[ipv6%zone]:port` or bare host.
+#[derive(Debug, PartialEq, Eq)]
+struct HostPort<'a> {
+    host: &'a str,
+    zone: Option<&'a str>,
+    port: u16,
+}
+
+impl<'a> HostPort<'a> {
+    fn parse(addr: &'a str, default_port: u16) -> Result<Self, Error> {
+        let invalid = || Error::InvalidSocketAddr {
+            addr: addr.to_owned(),
+        };
+        let parse_port = |port: &str| port.parse::<u16>().map_err(|_| invalid());
+
+        let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+            // A bracketed IPv6 address, with an optional port.
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            let port = match rest {
+                "" => default_port,
+                rest => parse_port(rest.strip_prefix(':').ok_or_else(invalid)?)?,
+            };
+            (host, port)
+        } else {
+            match addr.split_once(':') {
+                // More than one colon is a bare IPv6 address.
+                Some((_, port)) if port.contains(':') => (addr, default_port),
+                Some((host, port)) => (host, parse_port(port)?),
+                None => (addr, default_port),
+            }
+        };
+
+        let (host, zone) = match host.split_once('%') {
+            Some((host, zone)) => (host, Some(zone)),
+            None => (host, None),
+        };
+        if host.is_empty()
+            || zone.is_some_and(|zone| zone.is_empty() || Ipv6Addr::from_str(host).is_err())
+        {
+            return Err(invalid());
+        }
+        Ok(Self { host, zone, port })
+    }
+}
+
+/// Resolves the interface of a zone ID, which is either an index or a name.
+async fn scope_id(zone: &str) -> Result<u32, Error> {
+    if let Ok(index) = zone.parse() {
+        return Ok(index);
+    }
+    let path = format!("/sys/class/net/{zone}/ifindex");
+    let index = monoio::fs::read(path).await.ok();
+    let index = index
+        .as_deref()
+        .and_then(|index| std::str::from_utf8(index).ok());
+    index
+        .and_then(|index| index.trim().parse().ok())
+        .ok_or_else(|| Error::InvalidSocketAddr {
+            addr: zone.to_owned(),
+        })
+}
+
+impl StubResolver {
+    /// Resolves `host:port`, `ip:port`, `[ipv6]:port` (with an optional `%zone`), or a bare host
+    /// using the default port, into pairs of (socket address, ttl), like [`std::net::ToSocketAddrs`].
+    pub async fn resolve_socket_addrs<B>(
+        &self,
+        addr: impl AsRef<str>,
+        default_port: u16,
+    ) -> Result<B, Error>
+    where
+        B: FromIterator<(SocketAddr, Duration)>,
+    {
+        let HostPort { host, zone, port } = HostPort::parse(addr.as_ref(), default_port)?;
+
+        if let Some(zone) = zone {
+            let ip = Ipv6Addr::from_str(host)?;
+            let addr = SocketAddrV6::new(ip, port, 0, scope_id(zone).await?);
+            return Ok(std::iter::once((SocketAddr::V6(addr), Duration::ZERO)).collect());
+        }
+
+        if let Ok(ip) = IpAddr::from_str(host) {
+            return Ok(std::iter::once((SocketAddr::new(ip, port), Duration::ZERO)).collect());
+        }
+
+        let addrs: Vec<(IpAddr, Duration)> = self.lookup(host).await?;
+        Ok(addrs
+            .into_iter()
+            .map(|(ip, ttl)| (SocketAddr::new(ip, port), ttl))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(addr: &str) -> Option<(&str, Option<&str>, u16)> {
+        let HostPort { host, zone, port } = HostPort::parse(addr, 443).ok()?;
+        Some((host, zone, port))
+    }
+
+    #[test]
+    fn host_and_port() {
+        assert_eq!(parse("example.com:80"), Some(("example.com", None, 80)));
+        assert_eq!(parse("example.com"), Some(("example.com", None, 443)));
+        assert_eq!(parse("192.0.2.1:53"), Some(("192.0.2.1", None, 53)));
+        assert_eq!(parse("192.0.2.1"), Some(("192.0.2.1", None, 443)));
+    }
+
+    #[test]
+    fn ipv6() {
+        assert_eq!(parse("[2001:db8::1]:853"), Some(("2001:db8::1", None, 853)));
+        assert_eq!(parse("[2001:db8::1]"), Some(("2001:db8::1", None, 443)));
+        assert_eq!(parse("2001:db8::1"), Some(("2001:db8::1", None, 443)));
+        assert_eq!(parse("::1"), Some(("::1", None, 443)));
+    }
+
+    #[test]
+    fn zone() {
+        assert_eq!(
+            parse("[fe80::1%eth0]:53"),
+            Some(("fe80::1", Some("eth0"), 53))
+        );
+        assert_eq!(parse("[fe80::1%2]"), Some(("fe80::1", Some("2"), 443)));
+        assert_eq!(parse("fe80::1%eth0"), Some(("fe80::1", Some("eth0"), 443)));
+    }
+
+    #[test]
+    fn invalid() {
+        for addr in [
+            "",
+            ":80",
+            "example.com:",
+            "example.com:65536",
+            "example.com:http",
+            "[2001:db8::1",
+            "[2001:db8::1]:",
+            "[2001:db8::1]53",
+            "[]:53",
+            "[fe80::1%]:53",
+            "192.0.2.1%eth0",
+            "example.com%eth0:80",
+        ] {
+            assert_eq!(parse(addr), None, "{addr}");
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    cmp::{Ordering, Reverse},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    str::FromStr,
+    time::Duration,
+};
+
+use bstr::ByteSlice;
+
+use crate::errors::Error;
+
+const LABEL: &[u8] = "label".as_bytes();
+const PRECEDENCE: &[u8] = "precedence".as_bytes();
+
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    prefix: Ipv6Addr,
+    len: u8,
+    value: u32,
+}
+
+impl Entry {
+    const fn new(prefix: Ipv6Addr, len: u8, value: u32) -> Self {
+        Self { prefix, len, value }
+    }
+
+    fn matches(&self, addr: &Ipv6Addr) -> bool {
+        common_prefix_len(&self.prefix, addr) >= u32::from(self.len)
+    }
+}
+
+/// The policy table of RFC 6724, used to order the addresses of a lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyTable {
+    labels: Vec<Entry>,
+    precedences: Vec<Entry>,
+}
+
+impl Default for PolicyTable {
+    /// The default policy table of RFC 6724, section 2.1.
+    fn default() -> Self {
+        let defaults = [
+            (Ipv6Addr::LOCALHOST, 128, 50, 0),
+            (Ipv6Addr::UNSPECIFIED, 0, 40, 1),
+            (Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96, 35, 4),
+            (Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0), 16, 30, 2),
+            (Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0), 32, 5, 5),
+            (Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7, 3, 13),
+            (Ipv6Addr::UNSPECIFIED, 96, 1, 3),
+            (Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 0), 10, 1, 11),
+            (Ipv6Addr::new(0x3ffe, 0, 0, 0, 0, 0, 0, 0), 16, 1, 12),
+        ];
+        Self {
+            labels: defaults
+                .iter()
+                .map(|&(prefix, len, _, label)| Entry::new(prefix, len, label))
+                .collect(),
+            precedences: defaults
+                .iter()
+                .map(|&(prefix, len, precedence, _)| Entry::new(prefix, len, precedence))
+                .collect(),
+        }
+    }
+}
+
+impl PolicyTable {
+    /// An empty policy table, see [`PolicyTable::default`] for the one of RFC 6724.
+    pub fn empty() -> Self {
+        Self {
+            labels: Vec::new(),
+            precedences: Vec::new(),
+        }
+    }
+
+    /// Labels the prefix, overriding any previous label of the same prefix.
+    pub fn label(mut self, prefix: Ipv6Addr, len: u8, label: u32) -> Self {
+        self.labels.push(Entry::new(prefix, len.min(128), label));
+        self
+    }
+
+    /// Sets the precedence of the prefix, overriding any previous precedence of the same prefix.
+    pub fn precedence(mut self, prefix: Ipv6Addr, len: u8, precedence: u32) -> Self {
+        self.precedences
+            .push(Entry::new(prefix, len.min(128), precedence));
+        self
+    }
+
+    /// Parses the `label` and `precedence` directives of `/etc/gai.conf`.
+    ///
+    /// Like glibc, the directives of each kind replace the default ones rather than extend them.
+    pub fn from_gai_conf(content: &[u8]) -> Result<Self, Error> {
+        let mut this = Self::empty();
+        for line in content.lines() {
+            let mut it = line.fields().take_while(|field| !field.starts_with(b"#"));
+            let directive = it.next();
+            if !matches!(directive, Some(LABEL | PRECEDENCE)) {
+                continue;
+            }
+            let (Some(prefix), Some(value)) = (it.next(), it.next()) else {
+                continue;
+            };
+            let (addr, len) = prefix.split_once_str("/").unwrap_or((prefix, b"128"));
+            // Like glibc, the lines with a malformed prefix or value are skipped.
+            let addr = addr
+                .to_str()
+                .ok()
+                .and_then(|addr| Ipv6Addr::from_str(addr).ok());
+            let len = len.to_str().ok().and_then(|len| len.parse().ok());
+            let value = value.to_str().ok().and_then(|value| value.parse().ok());
+            let (Some(addr), Some(len @ 0..=128), Some(value)) = (addr, len, value) else {
+                continue;
+            };
+            this = match directive {
+                Some(LABEL) => this.label(addr, len, value),
+                _ => this.precedence(addr, len, value),
+            };
+        }
+
+        let defaults = Self::default();
+        if this.labels.is_empty() {
+            this.labels = defaults.labels;
+        }
+        if this.precedences.is_empty() {
+            this.precedences = defaults.precedences;
+        }
+        Ok(this)
+    }
+
+    fn lookup(entries: &[Entry], addr: &Ipv6Addr) -> u32 {
+        entries
+            .iter()
+            .filter(|entry| entry.matches(addr))
+            // The last of the longest prefixes wins.
+            .max_by_key(|entry| entry.len)
+            .map_or(0, |entry| entry.value)
+    }
+
+    fn label_of(&self, addr: &Ipv6Addr) -> u32 {
+        Self::lookup(&self.labels, addr)
+    }
+
+    fn precedence_of(&self, addr: &Ipv6Addr) -> u32 {
+        Self::lookup(&self.precedences, addr)
+    }
+
+    /// Orders the addresses following the destination address selection rules of RFC 6724, section 6.
+    pub fn sort(&self, addrs: &mut [(IpAddr, Duration)]) {
+        let mut candidates: Vec<Candidate> = addrs
+            .iter()
+            .map(|&(destination, ttl)| Candidate::new(self, destination, ttl))
+            .collect();
+        sort_candidates(&mut candidates);
+        for (slot, candidate) in addrs.iter_mut().zip(candidates) {
+            *slot = (candidate.destination, candidate.ttl);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Candidate {
+    destination: IpAddr,
+    ttl: Duration,
+    dst: Ipv6Addr,
+    src: Option<Ipv6Addr>,
+    dst_scope: u8,
+    src_scope: u8,
+    dst_label: u32,
+    src_label: u32,
+    precedence: u32,
+}
+
+impl Candidate {
+    fn new(table: &PolicyTable, destination: IpAddr, ttl: Duration) -> Self {
+        Self::with_source(table, destination, ttl, source_addr(destination))
+    }
+
+    /// The candidate with the given source address, `None` if the destination is unreachable.
+    fn with_source(
+        table: &PolicyTable,
+        destination: IpAddr,
+        ttl: Duration,
+        source: Option<IpAddr>,
+    ) -> Self {
+        let dst = to_ipv6(destination);
+        let src = source.map(to_ipv6);
+        Self {
+            destination,
+            ttl,
+            dst,
+            src,
+            dst_scope: scope(&dst),
+            src_scope: src.as_ref().map_or(0, scope),
+            dst_label: table.label_of(&dst),
+            src_label: src.as_ref().map_or(u32::MAX, |src| table.label_of(src)),
+            precedence: table.precedence_of(&dst),
+        }
+    }
+
+    fn prefix_len(&self) -> u32 {
+        self.src.map_or(0, |src| common_prefix_len(&self.dst, &src))
+    }
+}
+
+/// Orders the candidates following rules 1 to 10.
+fn sort_candidates(candidates: &mut [Candidate]) {
+    candidates.sort_by(compare);
+    // Rule 9 only orders addresses of the same family, which is not a total order across families.
+    // It is applied within each run of ties instead, among the positions of each family.
+    let mut start = 0;
+    while start < candidates.len() {
+        let run = candidates[start..]
+            .iter()
+            .position(|candidate| compare(&candidates[start], candidate) != Ordering::Equal)
+            .unwrap_or(candidates.len() - start);
+        longest_matching_prefix(&mut candidates[start..start + run]);
+        start += run;
+    }
+}
+
+/// Returns `Less` if `a` is preferred over `b`, following rules 1 to 8.
+fn compare(a: &Candidate, b: &Candidate) -> Ordering {
+    // Rule 1: Avoid unusable destinations.
+    let ordering = b.src.is_some().cmp(&a.src.is_some());
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+    // Rule 2: Prefer matching scope.
+    let ordering = (b.dst_scope == b.src_scope).cmp(&(a.dst_scope == a.src_scope));
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+    // Rule 5: Prefer matching label.
+    let ordering = (b.dst_label == b.src_label).cmp(&(a.dst_label == a.src_label));
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+    // Rule 6: Prefer higher precedence.
+    let ordering = b.precedence.cmp(&a.precedence);
+    if ordering != Ordering::Equal {
+        return ordering;
+    }
+    // Rule 8: Prefer smaller scope.
+    a.dst_scope.cmp(&b.dst_scope)
+}
+
+/// Rule 9: Use longest matching prefix, within the same address family.
+///
+/// The addresses of each family are reordered among the positions of that family, so that the families stay
+/// interleaved as they were. Rule 10: Otherwise, leave the order unchanged.
+fn longest_matching_prefix(candidates: &mut [Candidate]) {
+    for ipv4 in [false, true] {
+        let positions: Vec<usize> = (0..candidates.len())
+            .filter(|&idx| candidates[idx].destination.is_ipv4() == ipv4)
+            .collect();
+        let mut family: Vec<Candidate> = positions.iter().map(|&idx| candidates[idx]).collect();
+        family.sort_by_key(|candidate| Reverse(candidate.prefix_len()));
+        for (idx, candidate) in positions.into_iter().zip(family) {
+            candidates[idx] = candidate;
+        }
+    }
+}
+
+/// The source address the system would use to reach the destination, if it is reachable at all.
+fn source_addr(destination: IpAddr) -> Option<IpAddr> {
+    let bind: SocketAddr = match destination {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    // Connecting a UDP socket only picks a route, no packets are sent.
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect((destination, 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+fn to_ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+fn scope(addr: &Ipv6Addr) -> u8 {
+    let segments = addr.segments();
+    if let Some(addr) = addr.to_ipv4_mapped() {
+        // IPv4 loopback and link-local addresses have link-local scope, RFC 6724 section 3.2.
+        return if addr.is_loopback() || addr.is_link_local() {
+            SCOPE_LINK_LOCAL
+        } else {
+            SCOPE_GLOBAL
+        };
+    }
+    if addr.is_multicast() {
+        (segments[0] & 0x000f) as u8
+    } else if addr.is_loopback() || segments[0] & 0xffc0 == 0xfe80 {
+        SCOPE_LINK_LOCAL
+    } else if segments[0] & 0xffc0 == 0xfec0 {
+        SCOPE_SITE_LOCAL
+    } else {
+        SCOPE_GLOBAL
+    }
+}
+
+fn common_prefix_len(a: &Ipv6Addr, b: &Ipv6Addr) -> u32 {
+    (u128::from(*a) ^ u128::from(*b)).leading_zeros()
+}
+
+/// Moves the IPv4 addresses that match the `sortlist` of `/etc/resolv.conf` first, in the order of the list.
+///
+/// The order of the addresses that match the same entry, or none, is kept.
+pub(crate) fn apply_sortlist(sortlist: &[(Ipv4Addr, Ipv4Addr)], addrs: &mut [(IpAddr, Duration)]) {
+    if sortlist.is_empty() {
+        return;
+    }
+    addrs.sort_by_key(|(addr, _)| match addr {
+        IpAddr::V4(addr) => sortlist
+            .iter()
+            .position(|(network, mask)| {
+                u32::from(*addr) & u32::from(*mask) == u32::from(*network) & u32::from(*mask)
+            })
+            .unwrap_or(sortlist.len()),
+        IpAddr::V6(_) => sortlist.len(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sorts the destinations, each with its source address, with the default policy table.
+    fn sorted(destinations: &[(&str, Option<&str>)]) -> Vec<IpAddr> {
+        let table = PolicyTable::default();
+        let mut candidates: Vec<Candidate> = destinations
+            .iter()
+            .map(|&(dst, src)| {
+                let src = src.map(|src| src.parse().unwrap());
+                Candidate::with_source(&table, dst.parse().unwrap(), Duration::ZERO, src)
+            })
+            .collect();
+        sort_candidates(&mut candidates);
+        candidates
+            .iter()
+            .map(|candidate| candidate.destination)
+            .collect()
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn unusable_destinations() {
+        let result = sorted(&[
+            ("2001:db8:1::1", None),
+            ("2001:db8:2::1", Some("2001:db8:2::2")),
+        ]);
+        assert_eq!(result, addrs(&["2001:db8:2::1", "2001:db8:1::1"]));
+    }
+
+    #[test]
+    fn matching_scope() {
+        let result = sorted(&[
+            ("fe80::1", Some("2001:db8::2")),
+            ("2001:db8::1", Some("2001:db8::2")),
+        ]);
+        assert_eq!(result, addrs(&["2001:db8::1", "fe80::1"]));
+    }
+
+    #[test]
+    fn matching_label() {
+        // The 6to4 destination has a lower precedence, but its label matches the one of the source.
+        let result = sorted(&[
+            ("2001:db8:1::1", Some("2002:c633:6401::2")),
+            ("2002:c633:6401::1", Some("2002:c633:6401::2")),
+        ]);
+        assert_eq!(result, addrs(&["2002:c633:6401::1", "2001:db8:1::1"]));
+    }
+
+    #[test]
+    fn higher_precedence() {
+        let result = sorted(&[
+            ("198.51.100.121", Some("198.51.100.117")),
+            ("2001:db8:1::1", Some("2001:db8:1::2")),
+        ]);
+        assert_eq!(result, addrs(&["2001:db8:1::1", "198.51.100.121"]));
+    }
+
+    #[test]
+    fn smaller_scope() {
+        let result = sorted(&[
+            ("2001:db8:1::1", Some("2001:db8:1::2")),
+            ("fe80::1", Some("fe80::2")),
+        ]);
+        assert_eq!(result, addrs(&["fe80::1", "2001:db8:1::1"]));
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let result = sorted(&[
+            ("2001:db8:3ffe::1", Some("2001:db8:1::2")),
+            ("2001:db8:1::1", Some("2001:db8:1::2")),
+        ]);
+        assert_eq!(result, addrs(&["2001:db8:1::1", "2001:db8:3ffe::1"]));
+    }
+
+    #[test]
+    fn gai_conf() {
+        let content = b"\
+label ::1/128 7
+label not-an-address 3
+label ::/0 1 # comment
+precedence 2001:db8::/200 9
+precedence 2001:db8::/32 x
+precedence ::/0 4
+";
+        let table = PolicyTable::from_gai_conf(content).unwrap();
+        assert_eq!(table.labels.len(), 2);
+        assert_eq!(table.label_of(&Ipv6Addr::LOCALHOST), 7);
+        assert_eq!(table.precedences, [Entry::new(Ipv6Addr::UNSPECIFIED, 0, 4)]);
+    }
+}
@@ -0,0 +1,219 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use domain::{
+    base::{Message, ParsedName, Rtype},
+    rdata,
+};
+
+use crate::{errors::Error, LookupOptions, StubResolver};
+
+/// A target of a service, see [`StubResolver::lookup_srv`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+    pub ttl: Duration,
+    /// Pairs of (ip, ttl) of the target.
+    pub addrs: Vec<(IpAddr, Duration)>,
+}
+
+impl StubResolver {
+    /// Looks up the targets of a service (e.g. `_http._tcp.example.com`) following RFC 2782.
+    ///
+    /// The targets are ordered by priority, with a weighted random order among the targets of the same priority.
+    /// Their addresses are taken from the additional section when present, and looked up otherwise.
+    /// A target whose lookup fails is returned without addresses, rather than failing the other targets.
+    /// The name is not expanded with the search domains, and an empty list is returned if the service
+    /// is not available (i.e. its target is `.`), or if it has no SRV records.
+    pub async fn lookup_srv(&self, name: impl AsRef<str>) -> Result<Vec<SrvTarget>, Error> {
+        let message = self
+            .query(name, Rtype::SRV, LookupOptions::default())
+            .await?;
+        let mut targets = parse_targets(&message)?;
+        let additional = parse_additional(&message);
+        for target in targets.iter_mut() {
+            target.addrs = match additional.get(&target.target.to_ascii_lowercase()) {
+                Some(addrs) => addrs.clone(),
+                // The target is a fully qualified name, it must not be expanded with the search domains.
+                None => self
+                    .lookup(format!("{}.", target.target))
+                    .await
+                    .unwrap_or_default(),
+            };
+        }
+        Ok(order(targets))
+    }
+}
+
+fn parse_targets(message: &Message<Vec<u8>>) -> Result<Vec<SrvTarget>, Error> {
+    let mut targets = Vec::new();
+    for record in message.answer()?.flatten() {
+        if record.rtype() != Rtype::SRV {
+            continue;
+        }
+        let ttl = record.ttl().into_duration();
+        let Some(record) = record.into_record::<rdata::Srv<ParsedName<&[u8]>>>()? else {
+            continue;
+        };
+        let data = record.data();
+        let target = data.target().to_string();
+        let target = target.trim_end_matches('.');
+        // A target of `.` means that the service is decidedly not available.
+        if target.is_empty() {
+            continue;
+        }
+        targets.push(SrvTarget {
+            priority: data.priority(),
+            weight: data.weight(),
+            port: data.port(),
+            target: target.to_owned(),
+            ttl,
+            addrs: Vec::new(),
+        });
+    }
+    Ok(targets)
+}
+
+/// The addresses of the additional section, by lowercase owner name.
+fn parse_additional(message: &Message<Vec<u8>>) -> HashMap<String, Vec<(IpAddr, Duration)>> {
+    let mut addrs: HashMap<String, Vec<(IpAddr, Duration)>> = HashMap::new();
+    let Ok(additional) = message.additional() else {
+        return addrs;
+    };
+    for record in additional.flatten() {
+        let ttl = record.ttl().into_duration();
+        let owner = record.owner().to_string();
+        let owner = owner.trim_end_matches('.').to_ascii_lowercase();
+        let ip = match record.rtype() {
+            Rtype::A => match record.into_record::<rdata::A>() {
+                Ok(Some(record)) => IpAddr::V4(record.data().addr()),
+                _ => continue,
+            },
+            Rtype::AAAA => match record.into_record::<rdata::Aaaa>() {
+                Ok(Some(record)) => IpAddr::V6(record.data().addr()),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        addrs.entry(owner).or_default().push((ip, ttl));
+    }
+    addrs
+}
+
+/// Orders the targets by priority, and by weighted random selection within a priority (RFC 2782).
+fn order(mut targets: Vec<SrvTarget>) -> Vec<SrvTarget> {
+    targets.sort_by_key(|target| target.priority);
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].priority;
+        let end = targets
+            .iter()
+            .position(|target| target.priority != priority)
+            .unwrap_or(targets.len());
+        let mut group: Vec<SrvTarget> = targets.drain(..end).collect();
+        // Targets of weight 0 are placed first, so that they have a small chance of being selected.
+        group.sort_by_key(|target| target.weight != 0);
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|target| u32::from(target.weight)).sum();
+            let pick = fastrand::u32(..=total);
+            let mut sum = 0;
+            let idx = group
+                .iter()
+                .position(|target| {
+                    sum += u32::from(target.weight);
+                    sum >= pick
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(idx));
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, priority: u16, weight: u16) -> SrvTarget {
+        SrvTarget {
+            priority,
+            weight,
+            port: 443,
+            target: name.to_owned(),
+            ttl: Duration::ZERO,
+            addrs: Vec::new(),
+        }
+    }
+
+    fn names(targets: &[SrvTarget]) -> Vec<&str> {
+        targets
+            .iter()
+            .map(|target| target.target.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn by_priority() {
+        let targets = vec![target("c", 30, 5), target("a", 10, 0), target("b", 20, 0)];
+        assert_eq!(names(&order(targets)), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn zero_weights() {
+        for _ in 0..100 {
+            let targets = vec![
+                target("b1", 20, 0),
+                target("a1", 10, 0),
+                target("b2", 20, 0),
+                target("a2", 10, 0),
+            ];
+            let ordered = order(targets);
+            let mut first = names(&ordered[..2]);
+            let mut second = names(&ordered[2..]);
+            first.sort();
+            second.sort();
+            assert_eq!(first, ["a1", "a2"]);
+            assert_eq!(second, ["b1", "b2"]);
+        }
+    }
+
+    #[test]
+    fn zero_weight_is_rarely_first() {
+        let mut zero_first = 0;
+        for _ in 0..1000 {
+            let targets = vec![target("zero", 10, 0), target("heavy", 10, u16::MAX)];
+            let ordered = order(targets);
+            assert_eq!(ordered.len(), 2);
+            if ordered[0].target == "zero" {
+                zero_first += 1;
+            }
+        }
+        // The zero weight is picked first with a chance of 1 in 65536.
+        assert!(zero_first < 10, "{zero_first}");
+    }
+
+    #[test]
+    fn weighted() {
+        let mut light_first = 0;
+        for _ in 0..10_000 {
+            let targets = vec![target("light", 10, 1), target("heavy", 10, 3)];
+            if order(targets)[0].target == "light" {
+                light_first += 1;
+            }
+        }
+        // The draw is between 0 and the sum inclusive, so the first target also wins on 0: 2 times in 5.
+        assert!((3500..4500).contains(&light_first), "{light_first}");
+    }
+}
@@ -0,0 +1,164 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::Nameserver;
+
+/// Consecutive failures after which a nameserver is backed off.
+const BACKOFF_THRESHOLD: u32 = 2;
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Health and latency of a nameserver, as observed by the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameserverStats {
+    /// The smoothed round-trip time.
+    pub srtt: Duration,
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    /// The nameserver is only used as a last resort until then.
+    pub backoff_until: Option<Instant>,
+}
+
+impl Default for NameserverStats {
+    fn default() -> Self {
+        Self {
+            // Like BIND, start with a small random SRTT, so that untried servers get a chance first.
+            srtt: Duration::from_micros(fastrand::u64(1..=32_000)),
+            successes: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            backoff_until: None,
+        }
+    }
+}
+
+impl NameserverStats {
+    pub fn is_healthy(&self, now: Instant) -> bool {
+        self.backoff_until.map_or(true, |until| until <= now)
+    }
+
+    fn smooth(&mut self, rtt: Duration) {
+        // Saturates rather than overflowing on unreasonably long round-trips, e.g. with a huge timeout.
+        self.srtt = self
+            .srtt
+            .saturating_mul(7)
+            .saturating_add(rtt.saturating_mul(3))
+            / 10;
+    }
+
+    fn success(&mut self, rtt: Duration) {
+        self.smooth(rtt);
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.backoff_until = None;
+    }
+
+    fn failure(&mut self, penalty: Duration, now: Instant) {
+        self.smooth(penalty);
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= BACKOFF_THRESHOLD {
+            let exponent = (self.consecutive_failures - BACKOFF_THRESHOLD).min(16);
+            let backoff = (BACKOFF_MIN * (1 << exponent)).min(BACKOFF_MAX);
+            self.backoff_until = Some(now + backoff);
+        }
+    }
+
+    /// The exchange was abandoned after `elapsed`, which is a lower bound of the round-trip time.
+    fn abandoned(&mut self, elapsed: Duration) {
+        if elapsed > self.srtt {
+            self.smooth(elapsed);
+        }
+    }
+
+    /// Servers that are not used slowly become attractive again.
+    fn decay(&mut self) {
+        self.srtt = self.srtt.saturating_mul(98) / 100;
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    stats: RefCell<HashMap<SocketAddr, NameserverStats>>,
+    /// The number of selections made with `rotate`, which offsets the order of the nameservers.
+    rotation: Cell<usize>,
+}
+
+impl Stats {
+    /// Orders the enabled nameservers by preference: healthy ones first, by weight and then by SRTT,
+    /// followed by the backed off ones, by the end of their backoff.
+    ///
+    /// With `rotate`, the healthy ones are rather in the configured order, starting from the next nameserver
+    /// on every selection, like the `rotate` option of glibc.
+    pub(crate) fn select<'a>(
+        &self,
+        nameservers: &'a [Nameserver],
+        rotate: bool,
+    ) -> Vec<&'a Nameserver> {
+        let now = Instant::now();
+        let mut stats = self.stats.borrow_mut();
+        let mut selected: Vec<(&Nameserver, NameserverStats)> = nameservers
+            .iter()
+            .filter(|ns| ns.enabled)
+            .map(|ns| (ns, *stats.entry(ns.socket_addr()).or_default()))
+            .collect();
+        if rotate && !selected.is_empty() {
+            let rotation = self.rotation.get();
+            self.rotation.set(rotation.wrapping_add(1));
+            let mid = rotation % selected.len();
+            selected.rotate_left(mid);
+            selected.sort_by_key(|(_, stats)| stats.backoff_until.filter(|until| *until > now));
+        } else {
+            selected.sort_by_key(|(ns, stats)| match stats.backoff_until {
+                Some(until) if until > now => (true, Some(until), Reverse(0), Duration::ZERO),
+                _ => (false, None, Reverse(ns.weight), stats.srtt),
+            });
+        }
+        selected.into_iter().map(|(ns, _)| ns).collect()
+    }
+
+    pub(crate) fn success(&self, nameserver: SocketAddr, rtt: Duration) {
+        let mut stats = self.stats.borrow_mut();
+        for (addr, stats) in stats.iter_mut() {
+            if *addr != nameserver {
+                stats.decay();
+            }
+        }
+        stats.entry(nameserver).or_default().success(rtt);
+    }
+
+    pub(crate) fn failure(&self, nameserver: SocketAddr, penalty: Duration) {
+        let mut stats = self.stats.borrow_mut();
+        stats
+            .entry(nameserver)
+            .or_default()
+            .failure(penalty, Instant::now());
+    }
+
+    /// Records an exchange that was dropped before it completed, e.g. when another nameserver answered first,
+    /// without counting it as a failure.
+    pub(crate) fn abandoned(&self, nameserver: SocketAddr, elapsed: Duration) {
+        let mut stats = self.stats.borrow_mut();
+        stats.entry(nameserver).or_default().abandoned(elapsed);
+    }
+
+    pub(crate) fn get(&self, nameserver: SocketAddr) -> NameserverStats {
+        *self.stats.borrow_mut().entry(nameserver).or_default()
+    }
+}
@@ -0,0 +1,415 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use domain::base::{rdata::UnknownRecordData, Message, Rtype};
+
+use crate::{errors::Error, LookupOptions, StubResolver};
+
+const KEY_MANDATORY: u16 = 0;
+const KEY_ALPN: u16 = 1;
+const KEY_NO_DEFAULT_ALPN: u16 = 2;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4_HINT: u16 = 4;
+const KEY_ECH: u16 = 5;
+const KEY_IPV6_HINT: u16 = 6;
+
+/// How many aliases are followed before giving up.
+const MAX_ALIASES: usize = 8;
+
+/// A SVCB or HTTPS record (RFC 9460).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SvcbRecord {
+    /// 0 for AliasMode, ServiceMode otherwise, lower is preferred.
+    pub priority: u16,
+    /// The target name, empty for `.`.
+    pub target: String,
+    pub mandatory: Vec<u16>,
+    pub alpn: Vec<String>,
+    pub no_default_alpn: bool,
+    pub port: Option<u16>,
+    pub ipv4_hint: Vec<Ipv4Addr>,
+    pub ech: Option<Vec<u8>>,
+    pub ipv6_hint: Vec<Ipv6Addr>,
+    pub ttl: Duration,
+}
+
+impl SvcbRecord {
+    pub fn is_alias(&self) -> bool {
+        self.priority == 0
+    }
+
+    /// Parses the wire-format RDATA of a SVCB or HTTPS record.
+    pub fn parse(rdata: &[u8], ttl: Duration) -> Result<Self, Error> {
+        let mut parser = Parser(rdata);
+        let mut this = SvcbRecord {
+            priority: parser.u16()?,
+            target: parser.name()?,
+            ttl,
+            ..Default::default()
+        };
+        while !parser.0.is_empty() {
+            let key = parser.u16()?;
+            let len = parser.u16()?;
+            let mut value = Parser(parser.take(len as usize)?);
+            match key {
+                KEY_MANDATORY => {
+                    while !value.0.is_empty() {
+                        this.mandatory.push(value.u16()?);
+                    }
+                }
+                KEY_ALPN => {
+                    while !value.0.is_empty() {
+                        let len = value.u8()?;
+                        let id = value.take(len as usize)?;
+                        this.alpn.push(String::from_utf8_lossy(id).into_owned());
+                    }
+                }
+                KEY_NO_DEFAULT_ALPN => this.no_default_alpn = true,
+                KEY_PORT => this.port = Some(value.u16()?),
+                KEY_IPV4_HINT => {
+                    while !value.0.is_empty() {
+                        let octets: [u8; 4] = value.take(4)?.try_into().unwrap();
+                        this.ipv4_hint.push(Ipv4Addr::from(octets));
+                    }
+                }
+                KEY_ECH => this.ech = Some(value.take(value.0.len())?.to_vec()),
+                KEY_IPV6_HINT => {
+                    while !value.0.is_empty() {
+                        let octets: [u8; 16] = value.take(16)?.try_into().unwrap();
+                        this.ipv6_hint.push(Ipv6Addr::from(octets));
+                    }
+                }
+                // Unknown keys are ignored, unless they are mandatory.
+                _ => continue,
+            }
+            // The values of the known keys must have the exact length of their contents.
+            if !value.0.is_empty() {
+                return Err(Error::InvalidSvcb {});
+            }
+        }
+        Ok(this)
+    }
+
+    /// Parses the SVCB or HTTPS records of the answer section.
+    pub fn from_message(message: &Message<Vec<u8>>, rtype: Rtype) -> Result<Vec<Self>, Error> {
+        let mut records = Vec::new();
+        for record in message.answer()?.flatten() {
+            if record.rtype() != rtype {
+                continue;
+            }
+            let ttl = record.ttl().into_duration();
+            let record = record.into_any_record::<UnknownRecordData<&[u8]>>()?;
+            records.push(Self::parse(record.data().data(), ttl)?);
+        }
+        Ok(records)
+    }
+}
+
+struct Parser<'a>(&'a [u8]);
+
+impl<'a> Parser<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::InvalidSvcb {});
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// An uncompressed domain name.
+    fn name(&mut self) -> Result<String, Error> {
+        let mut name = String::new();
+        loop {
+            let len = self.u8()?;
+            if len == 0 {
+                return Ok(name);
+            }
+            if len > 63 {
+                return Err(Error::InvalidSvcb {});
+            }
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(&String::from_utf8_lossy(self.take(len as usize)?));
+        }
+    }
+}
+
+/// What to do with the SVCB or HTTPS RRset of a name.
+#[derive(Debug, PartialEq, Eq)]
+enum Selection {
+    /// The usable ServiceMode records, possibly none.
+    Services(Vec<SvcbRecord>),
+    /// Follow the alias to its target.
+    Alias(String),
+}
+
+/// Picks the alias of the RRset if there is one, otherwise its usable ServiceMode records ordered by priority,
+/// with their `.` targets replaced by the owner name.
+fn select(mut records: Vec<SvcbRecord>, owner: &str) -> Selection {
+    // The ServiceMode records of an RRset with an AliasMode record must be ignored, RFC 9460 section 2.4.2.
+    if let Some(alias) = records.iter().find(|record| record.is_alias()) {
+        return match alias.target.is_empty() {
+            // An alias to `.` means that the service is not available.
+            true => Selection::Services(Vec::new()),
+            false => Selection::Alias(alias.target.clone()),
+        };
+    }
+    // Records with unknown mandatory keys must be ignored.
+    records.retain(|record| {
+        record
+            .mandatory
+            .iter()
+            .all(|key| (KEY_ALPN..=KEY_IPV6_HINT).contains(key))
+    });
+    records.sort_by_key(|record| record.priority);
+    for record in records.iter_mut() {
+        if record.target.is_empty() {
+            record.target = owner.to_owned();
+        }
+    }
+    Selection::Services(records)
+}
+
+/// An endpoint of a service, see [`StubResolver::resolve_https`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SvcbEndpoint {
+    /// The record of the endpoint, `None` if the service has no HTTPS records.
+    pub record: Option<SvcbRecord>,
+    /// Pairs of (socket address, ttl).
+    pub addrs: Vec<(SocketAddr, Duration)>,
+}
+
+impl StubResolver {
+    /// Looks up the SVCB or HTTPS records of the name, following the aliases.
+    ///
+    /// Returns the ServiceMode records ordered by priority, with their `.` targets replaced by the owner name.
+    /// The ServiceMode records of an RRset that also has an AliasMode record are ignored, and the alias is followed.
+    pub async fn lookup_svcb(
+        &self,
+        name: impl AsRef<str>,
+        rtype: Rtype,
+    ) -> Result<Vec<SvcbRecord>, Error> {
+        let mut owner = name.as_ref().trim_end_matches('.').to_owned();
+        for _ in 0..MAX_ALIASES {
+            let message = self
+                .query(format!("{owner}."), rtype, LookupOptions::default())
+                .await?;
+            let records = SvcbRecord::from_message(&message, rtype)?;
+            match select(records, &owner) {
+                Selection::Services(services) => return Ok(services),
+                Selection::Alias(target) => owner = target,
+            }
+        }
+        Err(Error::InvalidSvcb {})
+    }
+
+    /// Looks up the HTTPS records of the host, and the addresses of their targets.
+    ///
+    /// The `ipv6hint` and `ipv4hint` addresses of a target are only used when the lookup of the target has no addresses
+    /// (RFC 9460 section 7.3), and the ports fall back to the given one. If the host has no HTTPS records, a single endpoint
+    /// with the addresses of the host is returned.
+    pub async fn resolve_https(
+        &self,
+        host: impl AsRef<str>,
+        port: u16,
+    ) -> Result<Vec<SvcbEndpoint>, Error> {
+        let host = host.as_ref().trim_end_matches('.');
+        // Port Prefix Naming, RFC 9460 section 9.1.
+        let name = match port {
+            443 => host.to_owned(),
+            port => format!("_{port}._https.{host}"),
+        };
+        let records = self.lookup_svcb(&name, Rtype::HTTPS).await?;
+
+        if records.is_empty() {
+            let addrs: Vec<(IpAddr, Duration)> = self.lookup(host).await?;
+            let addrs = addrs
+                .into_iter()
+                .map(|(ip, ttl)| (SocketAddr::new(ip, port), ttl))
+                .collect();
+            return Ok(vec![SvcbEndpoint {
+                record: None,
+                addrs,
+            }]);
+        }
+
+        let mut endpoints = Vec::with_capacity(records.len());
+        for mut record in records {
+            // A `.` target of a prefixed name refers to the host itself.
+            if record.target == name {
+                record.target = host.to_owned();
+            }
+            let port = record.port.unwrap_or(port);
+            let mut addrs: Vec<(IpAddr, Duration)> =
+                self.lookup(format!("{}.", record.target)).await?;
+            if addrs.is_empty() {
+                let hints = record.ipv6_hint.iter().map(|&ip| IpAddr::V6(ip));
+                let hints = hints.chain(record.ipv4_hint.iter().map(|&ip| IpAddr::V4(ip)));
+                addrs.extend(hints.map(|ip| (ip, record.ttl)));
+            }
+            let addrs = addrs
+                .into_iter()
+                .map(|(ip, ttl)| (SocketAddr::new(ip, port), ttl))
+                .collect();
+            endpoints.push(SvcbEndpoint {
+                record: Some(record),
+                addrs,
+            });
+        }
+        Ok(endpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RDATA of a ServiceMode record of priority 1 targeting `svc.example`, with the given parameters.
+    fn rdata(params: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut rdata = vec![0, 1, 3, b's', b'v', b'c', 7];
+        rdata.extend_from_slice(b"example");
+        rdata.push(0);
+        for (key, value) in params {
+            rdata.extend_from_slice(&key.to_be_bytes());
+            rdata.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(value);
+        }
+        rdata
+    }
+
+    fn parse(rdata: &[u8]) -> Result<SvcbRecord, Error> {
+        SvcbRecord::parse(rdata, Duration::from_secs(60))
+    }
+
+    #[test]
+    fn params() {
+        let record = parse(&rdata(&[
+            (KEY_MANDATORY, &[0, 1]),
+            (KEY_ALPN, b"\x02h2\x02h3"),
+            (KEY_NO_DEFAULT_ALPN, b""),
+            (KEY_PORT, &[0x01, 0xbb]),
+            (KEY_IPV4_HINT, &[192, 0, 2, 1, 192, 0, 2, 2]),
+            (KEY_ECH, &[1, 2, 3]),
+            (KEY_IPV6_HINT, &Ipv6Addr::LOCALHOST.octets()),
+            (65000, b"unknown"),
+        ]))
+        .unwrap();
+        assert_eq!(record.priority, 1);
+        assert_eq!(record.target, "svc.example");
+        assert_eq!(record.mandatory, [KEY_ALPN]);
+        assert_eq!(record.alpn, ["h2", "h3"]);
+        assert!(record.no_default_alpn);
+        assert_eq!(record.port, Some(443));
+        assert_eq!(
+            record.ipv4_hint,
+            [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)]
+        );
+        assert_eq!(record.ech.as_deref(), Some(&[1, 2, 3][..]));
+        assert_eq!(record.ipv6_hint, [Ipv6Addr::LOCALHOST]);
+        assert_eq!(record.ttl, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn alias() {
+        let record = parse(&[0, 0, 0]).unwrap();
+        assert!(record.is_alias());
+        assert!(record.target.is_empty());
+    }
+
+    fn record(priority: u16, target: &str) -> SvcbRecord {
+        SvcbRecord {
+            priority,
+            target: target.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn alias_over_services() {
+        let records = vec![
+            record(1, "svc.example"),
+            record(0, "alias.example"),
+            record(2, ""),
+        ];
+        let selection = select(records, "example.com");
+        assert_eq!(selection, Selection::Alias("alias.example".to_owned()));
+
+        // An alias to `.` makes the service unavailable, whatever the ServiceMode records.
+        let records = vec![record(1, "svc.example"), record(0, "")];
+        assert_eq!(
+            select(records, "example.com"),
+            Selection::Services(Vec::new())
+        );
+    }
+
+    #[test]
+    fn services() {
+        let mut unknown = record(1, "unknown.example");
+        unknown.mandatory = vec![65000];
+        let records = vec![record(3, ""), unknown, record(2, "svc.example")];
+        let expected = vec![record(2, "svc.example"), record(3, "example.com")];
+        assert_eq!(
+            select(records, "example.com"),
+            Selection::Services(expected)
+        );
+        assert_eq!(
+            select(Vec::new(), "example.com"),
+            Selection::Services(Vec::new())
+        );
+    }
+
+    #[test]
+    fn malformed_lengths() {
+        for params in [
+            [(KEY_MANDATORY, &[0, 1, 2][..])],
+            [(KEY_ALPN, b"\x03h2")],
+            [(KEY_NO_DEFAULT_ALPN, &[0])],
+            [(KEY_PORT, &[1])],
+            [(KEY_PORT, &[1, 187, 0])],
+            [(KEY_IPV4_HINT, &[192, 0, 2, 1, 192])],
+            [(KEY_IPV6_HINT, &[0; 17])],
+        ] {
+            assert!(parse(&rdata(&params)).is_err(), "{params:?}");
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let rdata = rdata(&[(KEY_PORT, &[0x01, 0xbb])]);
+        for len in 0..rdata.len() {
+            // Cutting between the target and the parameters leaves a valid record.
+            if len != 15 {
+                assert!(parse(&rdata[..len]).is_err(), "{len}");
+            }
+        }
+        // A value longer than the RDATA.
+        let mut rdata = rdata;
+        rdata[18] = 3;
+        assert!(parse(&rdata).is_err());
+        // A label longer than 63 bytes.
+        assert!(parse(&[0, 1, 64]).is_err());
+    }
+}
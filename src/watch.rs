@@ -0,0 +1,231 @@
+//
+// Copyright (c) 2024 Mohamed Seleem <oss@mselee.com>.
+//
+// This file is part of dnsaur.
+// See https://github.com/mselee/dnsaur for further info.
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+};
+
+use monoio::{fs::File, io::AsyncReadRent};
+use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+
+use crate::{errors::Error, StubResolver};
+
+const CONFIG_FILES: [&str; 4] = [
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/nsswitch.conf",
+    "/run/systemd/resolve/resolv.conf",
+];
+
+/// The size of the header of an `inotify_event`, followed by the name.
+const EVENT_HEADER: usize = 16;
+
+/// Watches `/etc/resolv.conf`, `/etc/hosts`, `/etc/nsswitch.conf` and the upstream `resolv.conf` of systemd-resolved
+/// for changes, using inotify.
+///
+/// The parent directories are watched rather than the files, so that atomic renames are noticed,
+/// as well as the targets of the files when they are symlinks.
+///
+/// [`StubResolver::watcher`] also watches the resolver directory of the resolver, if any.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), dnsaur::Error> {
+/// let dns = dnsaur::StubResolver::load().await?;
+/// let mut watcher = dnsaur::Watcher::new()?;
+/// loop {
+///     watcher.changed().await?;
+///     dns.reload().await?;
+/// }
+/// # }
+/// ```
+pub struct Watcher {
+    inotify: OwnedFd,
+    file: File,
+    /// The names of the watched files, by the watch descriptor of their directory.
+    watches: HashMap<i32, Vec<OsString>>,
+    /// The watch descriptors of the directories whose files are all watched.
+    dirs: HashSet<i32>,
+    resolver_dir: Option<PathBuf>,
+}
+
+impl std::fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher")
+            .field("inotify", &self.inotify)
+            .field("watches", &self.watches)
+            .field("dirs", &self.dirs)
+            .field("resolver_dir", &self.resolver_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Watcher {
+    pub fn new() -> Result<Self, Error> {
+        let inotify = inotify::init(CreateFlags::CLOEXEC).map_err(std::io::Error::from)?;
+        let file = File::from_std(std::fs::File::from(inotify.try_clone()?))?;
+        let mut this = Self {
+            inotify,
+            file,
+            watches: HashMap::new(),
+            dirs: HashSet::new(),
+            resolver_dir: None,
+        };
+        this.watch()?;
+        Ok(this)
+    }
+
+    /// Also watches the files of a resolver directory, see [`StubResolverBuilder::resolver_dir`].
+    ///
+    /// [`StubResolverBuilder::resolver_dir`]: crate::StubResolverBuilder::resolver_dir
+    pub fn resolver_dir(mut self, path: impl Into<PathBuf>) -> Result<Self, Error> {
+        self.resolver_dir = Some(path.into());
+        self.watch()?;
+        Ok(self)
+    }
+
+    /// (Re)watches the directories of the files and of their symlink targets.
+    ///
+    /// The directories that are no longer watched, such as those of previous symlink targets, are unwatched.
+    fn watch(&mut self) -> Result<(), Error> {
+        let mut previous: HashSet<i32> = self.watches.drain().map(|(wd, _)| wd).collect();
+        previous.extend(self.dirs.drain());
+        if let Some(dir) = self.resolver_dir.clone() {
+            // The directory itself, and its entry in its parent, should it be created or replaced.
+            self.watch_path(&dir)?;
+            match inotify::add_watch(&self.inotify, &dir, Self::flags()) {
+                Ok(wd) => {
+                    self.dirs.insert(wd);
+                }
+                Err(rustix::io::Errno::NOENT | rustix::io::Errno::NOTDIR) => {}
+                Err(err) => return Err(std::io::Error::from(err).into()),
+            }
+        }
+        for path in CONFIG_FILES {
+            let path = Path::new(path);
+            let mut paths = vec![path.to_owned()];
+            if let Ok(target) = path.canonicalize() {
+                if target != path {
+                    paths.push(target);
+                }
+            }
+            for path in paths {
+                self.watch_path(&path)?;
+            }
+        }
+        for wd in previous {
+            if !self.is_watched(wd) {
+                // The directory may be gone, and its watch already removed.
+                let _ = inotify::remove_watch(&self.inotify, wd);
+            }
+        }
+        Ok(())
+    }
+
+    fn watch_path(&mut self, path: &Path) -> Result<(), Error> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Ok(());
+        };
+        match inotify::add_watch(&self.inotify, dir, Self::flags()) {
+            Ok(wd) => {
+                let names = self.watches.entry(wd).or_default();
+                if !names.iter().any(|watched| watched == name) {
+                    names.push(name.to_owned());
+                }
+            }
+            // The directory of a symlink target may be gone, it is watched again on the next change.
+            Err(rustix::io::Errno::NOENT) => {}
+            Err(err) => return Err(std::io::Error::from(err).into()),
+        }
+        Ok(())
+    }
+
+    fn flags() -> WatchFlags {
+        WatchFlags::CLOSE_WRITE
+            | WatchFlags::MOVED_TO
+            | WatchFlags::MOVED_FROM
+            | WatchFlags::CREATE
+            | WatchFlags::DELETE
+            | WatchFlags::ONLYDIR
+    }
+
+    fn is_watched(&self, wd: i32) -> bool {
+        self.watches.contains_key(&wd) || self.dirs.contains(&wd)
+    }
+
+    /// Waits until one of the files has changed.
+    pub async fn changed(&mut self) -> Result<(), Error> {
+        loop {
+            let buf = Vec::with_capacity(4096);
+            // inotify is not seekable, it cannot be read at an offset.
+            let (result, buf) = self.file.read(buf).await;
+            let len = result?;
+            if self.is_relevant(&buf[..len]) {
+                // Symlinks may have been swapped.
+                self.watch()?;
+                return Ok(());
+            }
+        }
+    }
+
+    fn is_relevant(&self, mut events: &[u8]) -> bool {
+        let u32_at = |buf: &[u8], idx: usize| {
+            u32::from_ne_bytes([buf[idx], buf[idx + 1], buf[idx + 2], buf[idx + 3]])
+        };
+        while events.len() >= EVENT_HEADER {
+            let wd = u32_at(events, 0) as i32;
+            let mask = ReadFlags::from_bits_retain(u32_at(events, 4));
+            let len = u32_at(events, 12) as usize;
+            let Some(name) = events.get(EVENT_HEADER..EVENT_HEADER + len) else {
+                break;
+            };
+            events = &events[EVENT_HEADER + len..];
+
+            // Events were lost.
+            if mask.contains(ReadFlags::QUEUE_OVERFLOW) {
+                return true;
+            }
+            // A watched directory is gone, the watches removed by `watch` are not relevant.
+            if mask.contains(ReadFlags::IGNORED) {
+                if self.is_watched(wd) {
+                    return true;
+                }
+                continue;
+            }
+            let name = name.split(|&c| c == 0).next().unwrap_or_default();
+            // Hidden files, e.g. the temporary files of editors, are not read from the resolver directory.
+            if self.dirs.contains(&wd) && !name.starts_with(b".") {
+                return true;
+            }
+            let watched = self.watches.get(&wd).is_some_and(|names| {
+                names
+                    .iter()
+                    .any(|watched| watched.as_encoded_bytes() == name)
+            });
+            if watched {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl StubResolver {
+    /// A [`Watcher`] of the configuration files of the resolver, including its resolver directory.
+    pub fn watcher(&self) -> Result<Watcher, Error> {
+        let watcher = Watcher::new()?;
+        match &self.config().builder.resolver_dir {
+            Some(dir) => watcher.resolver_dir(dir),
+            None => Ok(watcher),
+        }
+    }
+}